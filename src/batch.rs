@@ -0,0 +1,53 @@
+use std::collections::HashMap;
+
+use crate::client::{ItemId, Recipe};
+use crate::error::{Error, Result};
+use crate::index::Index;
+use crate::pricing::{Buy, Sell, Strategy};
+use crate::solver;
+
+/// Profit from crafting a recipe repeatedly, as ingredient and output
+/// listings are used up.
+#[derive(Debug, Clone)]
+pub struct Batch {
+    /// Number of crafts that maximizes total profit; 0 if none is
+    /// profitable.
+    pub crafts: i32,
+    pub profit: i32,
+    /// Profit added by each craft in turn, as far as the market goes.
+    pub marginal: Vec<i32>,
+}
+
+/// Evaluates up to `max_crafts` crafts of `recipe`, buying ingredients and
+/// selling outputs together, and crafting no more than `limits` allows.
+/// Stops early once the output's buy side or an ingredient's sell side runs
+/// out, or the cheapest plan no longer crafts every unit with `recipe`.
+/// Recipes with random output aren't batched.
+///
+/// Only instant buying and selling is batched: an order is priced off the
+/// best listing alone, so it never uses up the market and every craft would
+/// look as good as the first.
+pub fn analyze(index: &Index, strategy: &Strategy, limits: &HashMap<ItemId, i32>, recipe: &Recipe, max_crafts: i32) -> Result<Batch> {
+    if strategy.buy == Buy::Order || strategy.sell == Sell::Order {
+        return Err(Error::Usage("batches need instant buying and selling; order prices don't account for market depth".into()));
+    }
+    let mut batch = Batch { crafts: 0, profit: 0, marginal: vec![] };
+    if !recipe.outcomes.is_empty() { return Ok(batch) }
+    let listings = if let Some(ls) = index.listings.get(&recipe.output_item_id) { ls } else { return Ok(batch) };
+    let mut last = 0;
+    for n in 1..=max_crafts {
+        let quantity = n * recipe.output_item_count;
+        let sale = if let Ok(s) = strategy.sale(listings, quantity) { s } else { break };
+        let cost = solver::solve(index, strategy, &recipe.output_item_id, quantity, &mut HashMap::new(), limits).cost;
+        if cost.source.crafted().is_none_or(|(id, _)| id != recipe.id) { break }
+        if cost.is_short() { break }
+        let profit = sale.net() - cost.total;
+        batch.marginal.push(profit - last);
+        last = profit;
+        if profit > batch.profit {
+            batch.crafts = n;
+            batch.profit = profit;
+        }
+    }
+    Ok(batch)
+}
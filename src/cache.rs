@@ -0,0 +1,48 @@
+use std::collections::HashMap;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::client::{Item, ItemId, Recipe, RecipeId};
+use crate::error::{self, Result};
+
+/// Recipes and item metadata persisted between runs.  Everything in the
+/// cache is discarded when the API build id changes.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Cache {
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    build: i32,
+    pub recipes: HashMap<RecipeId, Recipe>,
+    pub items: HashMap<ItemId, Item>,
+}
+
+impl Cache {
+    pub fn load(path: &Path, build: i32) -> Result<Cache> {
+        let mut cache = if path.exists() {
+            let file = std::fs::File::open(path)?;
+            error::from_json(&path.display().to_string(), BufReader::new(file))?
+        } else {
+            Cache::default()
+        };
+        if cache.build != build {
+            if cache.build != 0 {
+                println!("cache: build {} -> {}, discarding", cache.build, build);
+            }
+            cache = Cache { build, ..Cache::default() };
+        }
+        cache.path = Some(path.to_owned());
+        println!("cache: {} recipes, {} items", cache.recipes.len(), cache.items.len());
+        Ok(cache)
+    }
+
+    /// Writes the cache back to the file it was loaded from; a default
+    /// (in-memory) cache isn't saved.
+    pub fn save(&self) -> Result<()> {
+        let path = if let Some(p) = &self.path { p } else { return Ok(()) };
+        let file = std::fs::File::create(path)?;
+        serde_json::to_writer(BufWriter::new(file), self).map_err(std::io::Error::from)?;
+        Ok(())
+    }
+}
@@ -1,114 +1,448 @@
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
-use reqwest::{self, StatusCode};
-use serde::Deserialize;
+use futures::future;
+use futures::stream::{self, StreamExt};
+use rand::Rng;
+use reqwest::StatusCode;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use serde::{Deserialize, Serialize};
 use serde::de::{DeserializeOwned};
 
-use crate::error::{Result};
+use crate::error::{self, Error, Result};
+use crate::fee::Fill;
+use crate::transport::{Response, Transport};
 
+/// A handle to the API.  Clones, and clients made with `with_key`, share one
+/// transport and rate limiter, so they can be used concurrently.
+#[derive(Clone)]
 pub struct Client {
-    reqw: reqwest::blocking::Client,
-    last: Instant,
+    transport: Arc<dyn Transport>,
+    limiter: Arc<RateLimiter>,
+    retry: RetryPolicy,
+    key: Option<String>,
 }
 
-static KEY: &str = "9E827119-71EE-774C-88AE-B4D1D4B30868205CD293-9A69-415C-A3E3-4CB1E184722C";
-
 impl Client {
-    pub fn new() -> Self {
+    pub fn new(transport: Arc<dyn Transport>, key: Option<String>, retry: RetryPolicy) -> Self {
         Client {
-            reqw: reqwest::blocking::Client::new(),
-            last: Instant::now(),
+            transport,
+            limiter: Arc::new(RateLimiter::new()),
+            retry,
+            key,
         }
     }
 
-    pub fn characters(&mut self) -> Result<Vec<String>> {
-        self.fetch(true, "characters")
+    /// A client for another account that shares this one's rate limit.
+    pub fn with_key(&self, key: Option<String>) -> Self {
+        Client { key, ..self.clone() }
+    }
+
+    pub async fn tokeninfo(&self) -> Result<TokenInfo> {
+        self.fetch(true, "tokeninfo").await
+    }
+
+    pub async fn build(&self) -> Result<Build> {
+        self.fetch(false, "build").await
+    }
+
+    pub async fn account(&self) -> Result<Account> {
+        self.fetch(true, "account").await
+    }
+
+    pub async fn characters(&self) -> Result<Vec<String>> {
+        self.fetch(true, "characters").await
+    }
+
+    pub async fn character_recipes(&self, name: &str) -> Result<CharacterRecipes> {
+        self.fetch(true, &format!("characters/{}/recipes", name)).await
     }
 
-    pub fn character_recipes(&mut self, name: &str) -> Result<CharacterRecipes> {
-        self.fetch(true, &format!("characters/{}/recipes", name))
+    pub async fn recipes(&self, ids: &[RecipeId]) -> Result<Bulk<Recipe>> {
+        self.fetch_bulk("recipes", ids).await
     }
 
-    pub fn recipes(&mut self, ids: &[RecipeId]) -> Result<Vec<Recipe>> {
-        self.fetch(false, &format!("recipes?ids={}", ids_str(ids)))
+    pub async fn prices(&self, ids: &[ItemId]) -> Result<Bulk<Price>> {
+        self.fetch_bulk("commerce/prices", ids).await
     }
 
-    #[allow(unused)]
-    pub fn prices(&mut self, ids: &[ItemId]) -> Result<Vec<Price>> {
-        self.fetch(false, &format!("commerce/prices?ids={}", ids_str(ids)))
+    pub async fn items(&self, ids: &[ItemId]) -> Result<Bulk<Item>> {
+        self.fetch_bulk("items", ids).await
     }
 
-    pub fn items(&mut self, ids: &[ItemId]) -> Result<Vec<Item>> {
-        self.fetch(false, &format!("items?ids={}", ids_str(ids)))
+    pub async fn materials(&self) -> Result<Vec<Material>> {
+        self.fetch(true, "account/materials").await
     }
 
-    pub fn materials(&mut self) -> Result<Vec<Material>> {
-        self.fetch(true, "account/materials")
+    pub async fn all_currencies(&self) -> Result<Vec<CurrencyId>> {
+        self.fetch(false, "currencies").await
     }
 
-    pub fn all_recipes(&mut self) -> Result<Vec<RecipeId>> {
-        self.fetch(false, "recipes")
+    pub async fn currencies(&self, ids: &[CurrencyId]) -> Result<Bulk<Currency>> {
+        self.fetch_bulk("currencies", ids).await
     }
 
-    pub fn listings(&mut self, ids: &[ItemId]) -> Result<Vec<Listings>> {
-        let mut out: Vec<Listings> = self.fetch(false, &format!("commerce/listings?ids={}", ids_str(ids)))?;
-        for ls in &mut out {
+    pub async fn wallet(&self) -> Result<Vec<WalletEntry>> {
+        self.fetch(true, "account/wallet").await
+    }
+
+    /// Ids of the time-gated crafts, e.g. "lump_of_mithrillium".
+    pub async fn daily_crafting(&self) -> Result<Vec<String>> {
+        self.fetch(false, "dailycrafting").await
+    }
+
+    /// Ids of the once-a-day map chests.
+    pub async fn map_chests(&self) -> Result<Vec<String>> {
+        self.fetch(false, "mapchests").await
+    }
+
+    /// Time-gated crafts the account has done since the daily reset.
+    pub async fn account_daily_crafting(&self) -> Result<Vec<String>> {
+        self.fetch(true, "account/dailycrafting").await
+    }
+
+    /// Map chests the account has opened since the daily reset.
+    pub async fn account_map_chests(&self) -> Result<Vec<String>> {
+        self.fetch(true, "account/mapchests").await
+    }
+
+    pub async fn listings(&self, ids: &[ItemId]) -> Result<Bulk<Listings>> {
+        let mut out: Bulk<Listings> = self.fetch_bulk("commerce/listings", ids).await?;
+        for ls in &mut out.found {
             ls.buys.sort_by(|a, b| b.unit_price.cmp(&a.unit_price));
             ls.sells.sort_by(|a, b| a.unit_price.cmp(&b.unit_price));
         }
         Ok(out)
     }
 
-    fn fetch<Out>(
-        &mut self,
+    /// Gems bought for `coins` copper.
+    pub async fn exchange_coins(&self, coins: i32) -> Result<Exchange> {
+        self.fetch(false, &format!("commerce/exchange/coins?quantity={}", coins)).await
+    }
+
+    /// Copper bought for `gems` gems.
+    pub async fn exchange_gems(&self, gems: i32) -> Result<Exchange> {
+        self.fetch(false, &format!("commerce/exchange/gems?quantity={}", gems)).await
+    }
+
+    /// Coins and items waiting to be picked up from the trading post.
+    pub async fn delivery(&self) -> Result<Delivery> {
+        self.fetch(true, "commerce/delivery").await
+    }
+
+    /// All of the account's transactions of one kind, most recent first.
+    /// The API keeps 90 days of history.
+    pub async fn transactions(&self, state: TransactionState, side: Side) -> Result<Vec<Transaction>> {
+        self.all_pages(true, &format!("commerce/transactions/{}/{}", state.as_str(), side.as_str())).await
+    }
+
+    /// Requests `ids` from a bulk endpoint, in as many requests as needed.
+    /// The API answers 206 when only some ids are valid and 404 when none
+    /// are; either way the ids that didn't come back are reported in
+    /// `missing`.
+    pub async fn fetch_bulk<T>(&self, path: &str, ids: &[T::Id]) -> Result<Bulk<T>>
+    where
+        T: HasId + DeserializeOwned,
+    {
+        let mut out = Bulk::default();
+        let mut chunks = stream::iter(ids.chunks(MAX_PAGE_SIZE as usize))
+            .map(|chunk| self.fetch_chunk(path, chunk))
+            .buffer_unordered(CONCURRENCY);
+        while let Some(chunk) = chunks.next().await {
+            let chunk = chunk?;
+            out.found.extend(chunk.found);
+            out.missing.extend(chunk.missing);
+        }
+        Ok(out)
+    }
+
+    async fn fetch_chunk<T>(&self, path: &str, ids: &[T::Id]) -> Result<Bulk<T>>
+    where
+        T: HasId + DeserializeOwned,
+    {
+        let found: Vec<T> = match self.fetch(false, &format!("{}?ids={}", path, ids_str(ids))).await {
+            Ok(f) => f,
+            Err(Error::Http { status: StatusCode::NOT_FOUND, .. }) => vec![],
+            Err(e) => return Err(e),
+        };
+        let returned: HashSet<T::Id> = found.iter().map(|t| t.id()).collect();
+        let missing = ids.iter().filter(|id| !returned.contains(id)).cloned().collect();
+        Ok(Bulk { found, missing })
+    }
+
+    /// One page of a paginated endpoint.  Pages are numbered from 0.
+    pub async fn fetch_page<T>(&self, auth: bool, path: &str, page: u32, page_size: u32) -> Result<Page<T>>
+    where
+        T: DeserializeOwned,
+    {
+        let sep = if path.contains('?') { '&' } else { '?' };
+        let path = format!("{}{}page={}&page_size={}", path, sep, page, page_size);
+        let res = self.fetch_response(auth, &path).await?;
+        let header = |name: &str| res.headers.get(name)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u32>().ok());
+        let page_total = header("X-Page-Total").unwrap_or(1);
+        let result_total = header("X-Result-Total");
+        let items: Vec<T> = error::from_json(&path, &res.body[..])?;
+        let result_total = result_total.unwrap_or(items.len() as u32);
+        Ok(Page { items, page_total, result_total })
+    }
+
+    /// Every page of a paginated endpoint, in order, one request at a time.
+    /// For bulk endpoints like `recipes` this walks the whole collection.
+    pub fn pages<'a, T>(&'a self, auth: bool, path: &'a str, page_size: u32) -> impl futures::Stream<Item=Result<Vec<T>>> + 'a
+    where
+        T: DeserializeOwned + 'a,
+    {
+        // State is the next page and, once known, the page count.
+        stream::unfold(Some((0, None)), move |state| async move {
+            let (page, total) = state?;
+            if total.is_some_and(|t| page >= t) {
+                return None;
+            }
+            match self.fetch_page::<T>(auth, path, page, page_size).await {
+                Ok(p) => Some((Ok(p.items), Some((page + 1, Some(p.page_total))))),
+                Err(e) => Some((Err(e), None)),
+            }
+        })
+    }
+
+    /// Everything from a paginated endpoint.  The first page gives the page
+    /// count; the rest are requested concurrently.
+    pub async fn all_pages<T>(&self, auth: bool, path: &str) -> Result<Vec<T>>
+    where
+        T: DeserializeOwned,
+    {
+        let first = self.fetch_page::<T>(auth, path, 0, MAX_PAGE_SIZE).await?;
+        let rest = future::try_join_all(
+            (1..first.page_total).map(|p| self.fetch_page::<T>(auth, path, p, MAX_PAGE_SIZE))
+        ).await?;
+        let mut out = Vec::with_capacity(first.result_total as usize);
+        out.extend(first.items);
+        for p in rest {
+            out.extend(p.items);
+        }
+        Ok(out)
+    }
+
+    async fn fetch<Out>(
+        &self,
         auth: bool,
         path: &str,
     ) -> Result<Out>
     where
         Out: DeserializeOwned,
     {
-        let since = Instant::now().duration_since(self.last);
-        let tick = Duration::from_secs_f32(0.1);
-        if since < tick {
-            std::thread::sleep(tick - since);
+        let res = self.fetch_response(auth, path).await?;
+        error::from_json(path, &res.body[..])
+    }
+
+    async fn fetch_response(&self, auth: bool, path: &str) -> Result<Response> {
+        let key = match (auth, &self.key) {
+            (false, _) => None,
+            (true, Some(k)) => Some(k.clone()),
+            (true, None) => return Err(Error::NoKey { path: path.to_string() }),
+        };
+
+        let mut attempt = 1;
+        let res = loop {
+            self.limiter.acquire().await;
+            let (err, retry_after) = match self.transport.get(path, key.as_deref()).await {
+                Ok(res) => match res.status {
+                    StatusCode::OK | StatusCode::PARTIAL_CONTENT => break res,
+                    s if is_transient(s) => {
+                        if s == StatusCode::TOO_MANY_REQUESTS {
+                            self.limiter.drain();
+                        }
+                        (status_error(path, s, &res.body), retry_after(&res.headers))
+                    }
+                    s => return Err(status_error(path, s, &res.body)),
+                },
+                Err(Error::Request(e)) if e.is_timeout() || e.is_request() => (Error::Request(e), None),
+                Err(e) => return Err(e),
+            };
+            if attempt >= self.retry.max_attempts {
+                return Err(err);
+            }
+            let delay = self.retry.delay(attempt, retry_after);
+            println!("\t{}, retry {}/{} in {:?}", err, attempt, self.retry.max_attempts - 1, delay);
+            tokio::time::delay_for(delay).await;
+            attempt += 1;
+        };
+        Ok(res)
+    }
+}
+
+/// The API's limit on both `page_size` and the number of `ids` per request.
+pub const MAX_PAGE_SIZE: u32 = 200;
+// Bulk requests in flight at once per call.
+const CONCURRENCY: usize = 8;
+
+/// One page of results from a paginated endpoint.
+#[derive(Debug, Clone)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub page_total: u32,
+    pub result_total: u32,
+}
+
+/// Error for a failed response, using the `text` message the API includes in
+/// error bodies.
+fn status_error(path: &str, status: StatusCode, body: &[u8]) -> Error {
+    #[derive(Deserialize)]
+    struct ErrorBody {
+        text: String,
+    }
+    let path = path.to_string();
+    let text = match serde_json::from_slice::<ErrorBody>(body) {
+        Ok(b) => b.text,
+        Err(_) => String::new(),
+    };
+    match status {
+        StatusCode::UNAUTHORIZED => Error::InvalidToken { path },
+        StatusCode::FORBIDDEN if text.contains("Invalid access token") => Error::InvalidToken { path },
+        StatusCode::FORBIDDEN => Error::InsufficientToken { path, text },
+        _ => Error::Http { path, status, text },
+    }
+}
+
+fn is_transient(status: StatusCode) -> bool {
+    matches!(status,
+        StatusCode::TOO_MANY_REQUESTS
+        | StatusCode::INTERNAL_SERVER_ERROR
+        | StatusCode::BAD_GATEWAY
+        | StatusCode::SERVICE_UNAVAILABLE
+        | StatusCode::GATEWAY_TIMEOUT)
+}
+
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let secs = headers.get(RETRY_AFTER)?.to_str().ok()?.trim().parse::<u64>().ok()?;
+    Some(Duration::from_secs(secs))
+}
+
+/// How transient failures (429s, 5xxs, timeouts) are retried.  Delays grow
+/// exponentially from `base_delay_ms`, capped at `max_delay_ms`, with jitter;
+/// a `Retry-After` header from the server is honored if it is longer.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Total attempts, including the first.
+    pub max_attempts: u32,
+    pub base_delay_ms: u64,
+    pub max_delay_ms: u64,
+    /// How long a request may take before it's abandoned and retried.
+    pub timeout_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 6,
+            base_delay_ms: 250,
+            max_delay_ms: 30_000,
+            timeout_ms: 30_000,
         }
-        self.last = Instant::now();
+    }
+}
+
+impl RetryPolicy {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
 
-        let mut req = self.reqw.get(&format!("https://api.guildwars2.com/v2/{}", path))
-            .query(&[("v", "latest")]);
-        if auth {
-            req = req.query(&[("access_token", KEY)]);
+    /// How long to wait after failed attempt number `attempt` (from 1).
+    pub fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        let exp = self.base_delay_ms.saturating_mul(1 << (attempt - 1).min(16));
+        let cap = exp.min(self.max_delay_ms);
+        // "Equal jitter": somewhere between half and all of the capped delay.
+        let ms = rand::thread_rng().gen_range(cap / 2, cap + 1);
+        let delay = Duration::from_millis(ms);
+        match retry_after {
+            Some(ra) if ra > delay => ra,
+            _ => delay,
         }
-        let req = req.build()?;
-        let mut res = self.reqw.execute(req.try_clone().unwrap())?;
-        if res.status() == StatusCode::TOO_MANY_REQUESTS {
-            println!("\t429 sleep");
-            std::thread::sleep(tick + tick);
-            self.last = Instant::now();
-            res = self.reqw.execute(req)?;
+    }
+}
+
+// The API allows bursts of up to 300 requests, refilling at 5 per second
+// (300 per minute) per IP.
+const BUCKET_SIZE: f64 = 300.0;
+const BUCKET_REFILL: f64 = 5.0;
+
+/// Token bucket shared by every request issued through a `Client`.
+struct RateLimiter {
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    fn new() -> Self {
+        RateLimiter {
+            bucket: Mutex::new(Bucket { tokens: BUCKET_SIZE, last: Instant::now() }),
         }
-        match res.status() {
-            StatusCode::OK => (),
-            StatusCode::PARTIAL_CONTENT => /*println!("[Partial {:?}]", res)*/ (),
-            _ => failed!("{:?}", res),
+    }
+
+    async fn acquire(&self) {
+        loop {
+            let wait = {
+                let mut b = self.bucket.lock().unwrap();
+                let now = Instant::now();
+                let refill = now.duration_since(b.last).as_secs_f64() * BUCKET_REFILL;
+                b.tokens = (b.tokens + refill).min(BUCKET_SIZE);
+                b.last = now;
+                if b.tokens >= 1.0 {
+                    b.tokens -= 1.0;
+                    return;
+                }
+                Duration::from_secs_f64((1.0 - b.tokens) / BUCKET_REFILL)
+            };
+            tokio::time::delay_for(wait).await;
         }
+    }
 
-        Ok(res.json()?)
+    /// Empties the bucket after the server reports the limit was hit, so all
+    /// outstanding requests back off.
+    fn drain(&self) {
+        self.bucket.lock().unwrap().tokens = 0.0;
     }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TokenInfo {
+    pub id: String,
+    pub name: String,
+    pub permissions: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Build {
+    pub id: i32,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Account {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct CharacterRecipes {
     pub recipes: Vec<RecipeId>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Ingredient {
     pub item_id: ItemId,
     pub count: i32,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Recipe {
     #[serde(rename = "type")]
     pub typ: String,
@@ -121,9 +455,41 @@ pub struct Recipe {
     pub ingredients: Vec<Ingredient>,
     pub id: RecipeId,
     pub chat_link: String,
+    /// Possible results of one craft when the output is random; empty when
+    /// every craft gives `output_item_count` of `output_item_id`.  Only
+    /// recipes from the forge data file have these.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub outcomes: Vec<Outcome>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+impl Recipe {
+    /// Units of `output_item_id` one craft gives on average.
+    pub fn expected_count(&self) -> f32 {
+        if self.outcomes.is_empty() {
+            return self.output_item_count as f32;
+        }
+        self.outcomes.iter()
+            .filter(|o| o.item_id == self.output_item_id)
+            .map(|o| o.chance * o.count as f32)
+            .sum()
+    }
+
+    /// Crafts needed to expect at least `quantity` of the output.
+    pub fn runs(&self, quantity: i32) -> i32 {
+        ((quantity as f32) / self.expected_count()).ceil() as i32
+    }
+}
+
+/// One possible result of a random recipe.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Outcome {
+    pub item_id: ItemId,
+    pub count: i32,
+    /// Probability of this result; a recipe's chances sum to 1.
+    pub chance: f32,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Price {
     pub id: ItemId,
     pub whitelisted: bool,
@@ -132,13 +498,13 @@ pub struct Price {
     pub vendor: Option<()>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Order {
     pub quantity: i32,
     pub unit_price: i32,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Item {
     pub name: String,
     pub description: Option<String>,
@@ -156,16 +522,67 @@ pub struct Item {
 }
 
 #[repr(transparent)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
 #[serde(transparent)]
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize)]
 pub struct RecipeId(pub i32);
 
 #[repr(transparent)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
 #[serde(transparent)]
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize)]
 pub struct ItemId(pub i32);
 
-trait AsId {
+#[repr(transparent)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct CurrencyId(pub i32);
+
+/// Objects returned by bulk (`?ids=`) endpoints.
+pub trait HasId {
+    type Id: AsId + Copy + Eq + Hash;
+    fn id(&self) -> Self::Id;
+}
+
+impl HasId for Recipe {
+    type Id = RecipeId;
+    fn id(&self) -> RecipeId { self.id }
+}
+
+impl HasId for Item {
+    type Id = ItemId;
+    fn id(&self) -> ItemId { self.id }
+}
+
+impl HasId for Price {
+    type Id = ItemId;
+    fn id(&self) -> ItemId { self.id }
+}
+
+impl HasId for Listings {
+    type Id = ItemId;
+    fn id(&self) -> ItemId { self.id }
+}
+
+impl HasId for Currency {
+    type Id = CurrencyId;
+    fn id(&self) -> CurrencyId { self.id }
+}
+
+/// The result of a bulk request.
+#[derive(Debug, Clone)]
+pub struct Bulk<T: HasId> {
+    pub found: Vec<T>,
+    /// Requested ids the API didn't return, because they're invalid or (for
+    /// the trading post) the item isn't tradeable.
+    pub missing: Vec<T::Id>,
+}
+
+impl<T: HasId> Default for Bulk<T> {
+    fn default() -> Self {
+        Bulk { found: vec![], missing: vec![] }
+    }
+}
+
+pub trait AsId {
     fn as_id(&self) -> i32;
 }
 
@@ -177,12 +594,94 @@ impl AsId for RecipeId {
     fn as_id(&self) -> i32 { self.0 }
 }
 
+impl AsId for CurrencyId {
+    fn as_id(&self) -> i32 { self.0 }
+}
+
 fn ids_str<T: AsId>(ids: &[T]) -> String {
     let id_strs: Vec<String> = ids.iter().map(|id| format!("{}", id.as_id())).collect();
     id_strs.join(",")
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Exchange {
+    pub coins_per_gem: i32,
+    /// Gems received for coins, or coins received for gems.
+    pub quantity: i32,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Delivery {
+    pub coins: i32,
+    pub items: Vec<DeliveryItem>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DeliveryItem {
+    pub id: ItemId,
+    pub count: i32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TransactionState {
+    /// Orders that haven't been fulfilled.
+    Current,
+    /// Orders fulfilled in the last 90 days.
+    History,
+}
+
+impl TransactionState {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TransactionState::Current => "current",
+            TransactionState::History => "history",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Side {
+    Buys,
+    Sells,
+}
+
+impl Side {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Side::Buys => "buys",
+            Side::Sells => "sells",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Transaction {
+    pub id: u64,
+    pub item_id: ItemId,
+    pub price: i32,
+    pub quantity: i32,
+    pub created: String,
+    /// Only for history.
+    pub purchased: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Currency {
+    pub id: CurrencyId,
+    pub name: String,
+    pub description: String,
+    pub order: i32,
+    pub icon: String,
+}
+
+/// An account's balance of one currency.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct WalletEntry {
+    pub id: CurrencyId,
+    pub value: i32,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Material {
     pub id: ItemId,
     pub category: i32,
@@ -190,7 +689,7 @@ pub struct Material {
     pub count: i32,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Listings {
     pub id: ItemId,
     pub buys: Vec<Listing>,
@@ -209,24 +708,64 @@ impl Listings {
                 return Ok(cost)
             }
         }
-        failed!("cost short {} of {}", remaining, self.id.0)
+        Err(Error::InsufficientDepth { item: self.id, shortfall: remaining })
     }
     pub fn sale(&self, quantity: i32) -> Result<i32> {
+        Ok(self.sale_fills(quantity)?.iter().map(|f| f.unit_price * f.quantity).sum())
+    }
+
+    /// The buy orders `quantity` would fill, best first.
+    pub fn sale_fills(&self, quantity: i32) -> Result<Vec<Fill>> {
         let mut remaining = quantity;
-        let mut sale = 0;
+        let mut fills = vec![];
         for l in &self.buys {
             let sold = std::cmp::min(remaining, l.quantity);
-            sale += sold * l.unit_price;
+            fills.push(Fill { unit_price: l.unit_price, quantity: sold });
             remaining -= sold;
             if remaining == 0 {
-                return Ok(sale)
+                return Ok(fills)
             }
         }
-        failed!("sale short {} of {}", remaining, self.id.0)
+        Err(Error::InsufficientDepth { item: self.id, shortfall: remaining })
+    }
+
+    /// Cost of `quantity` bought with a buy order placed 1c above the best
+    /// one, as long as that stays under the lowest sell listing.  With no
+    /// buy orders to outbid, this is the instant cost.
+    pub fn order_cost(&self, quantity: i32) -> Result<i32> {
+        let best = match self.buys.first() {
+            Some(l) => l.unit_price,
+            None => return self.cost(quantity),
+        };
+        let price = match self.sells.first() {
+            Some(l) if best + 1 >= l.unit_price => best,
+            _ => best + 1,
+        };
+        Ok(price * quantity)
+    }
+
+    /// Gross proceeds of `quantity` listed 1c under the lowest sell listing,
+    /// as long as that stays over the best buy order.
+    pub fn order_sale(&self, quantity: i32) -> Result<i32> {
+        Ok(self.order_sale_price(quantity)? * quantity)
+    }
+
+    /// Unit price `order_sale` lists at.  With no sell listings to
+    /// undercut, this is the best buy order's price.
+    pub fn order_sale_price(&self, quantity: i32) -> Result<i32> {
+        let lowest = match (self.sells.first(), self.buys.first()) {
+            (Some(l), _) => l.unit_price,
+            (None, Some(l)) => return Ok(l.unit_price),
+            (None, None) => return Err(Error::InsufficientDepth { item: self.id, shortfall: quantity }),
+        };
+        Ok(match self.buys.first() {
+            Some(l) if lowest - 1 <= l.unit_price => lowest,
+            _ => lowest - 1,
+        })
     }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Listing {
     pub listings: i32,
     pub unit_price: i32,
@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::client::RetryPolicy;
+use crate::error::{self, Error, Result};
+use crate::pricing::Strategy;
+
+static DEFAULT_PATH: &str = "rs-gw2.json";
+static KEY_VAR: &str = "GW2_API_KEY";
+static DEFAULT_CACHE: &str = "rs-gw2-cache.json";
+static DEFAULT_HISTORY: &str = "rs-gw2-history.jsonl";
+static DEFAULT_VENDOR: &str = "data/vendor.json";
+static DEFAULT_SPECIAL: &str = "data/special.json";
+static DEFAULT_FORGE: &str = "data/forge.json";
+
+/// Settings read from the config file, overridden by the environment and
+/// then by command-line flags.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    pub key: Option<String>,
+    /// Additional keys, one per account.
+    #[serde(default)]
+    pub keys: Vec<String>,
+    /// Where recipes and items are cached between runs.
+    #[serde(default = "default_cache")]
+    pub cache: PathBuf,
+    /// Where price snapshots are appended.
+    #[serde(default = "default_history")]
+    pub history: PathBuf,
+    /// Vendor price table.
+    #[serde(default = "default_vendor")]
+    pub vendor: PathBuf,
+    /// Valuation rules for items with no useful market price.
+    #[serde(default = "default_special")]
+    pub special: PathBuf,
+    /// Mystic Forge recipes.
+    #[serde(default = "default_forge")]
+    pub forge: PathBuf,
+    /// Copper value of a unit of each currency, by name, overriding the
+    /// vendor table's.
+    #[serde(default)]
+    pub currency_values: HashMap<String, f32>,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    /// How ingredients are bought and outputs sold.
+    #[serde(default)]
+    pub strategy: Strategy,
+    /// Index every recipe in the game, not just those the accounts'
+    /// characters know.
+    #[serde(default)]
+    pub all_recipes: bool,
+    /// Load the index from this snapshot instead of the API.
+    #[serde(skip)]
+    pub snapshot: Option<PathBuf>,
+    /// Write the index to this snapshot once built.
+    #[serde(skip)]
+    pub save_snapshot: Option<PathBuf>,
+    /// Save API responses as fixtures in this directory.
+    #[serde(skip)]
+    pub record: Option<PathBuf>,
+    /// Serve API responses from fixtures in this directory.
+    #[serde(skip)]
+    pub replay: Option<PathBuf>,
+    /// Record price snapshots every this many seconds instead of running
+    /// interactively; 0 records once and exits, for running from cron.
+    #[serde(skip)]
+    pub record_history: Option<u64>,
+}
+
+impl Default for Config {
+    // The serde defaults, so they're written down in one place.
+    fn default() -> Config {
+        serde_json::from_str("{}").expect("empty config")
+    }
+}
+
+fn default_cache() -> PathBuf {
+    PathBuf::from(DEFAULT_CACHE)
+}
+
+fn default_history() -> PathBuf {
+    PathBuf::from(DEFAULT_HISTORY)
+}
+
+fn default_vendor() -> PathBuf {
+    PathBuf::from(DEFAULT_VENDOR)
+}
+
+fn default_special() -> PathBuf {
+    PathBuf::from(DEFAULT_SPECIAL)
+}
+
+fn default_forge() -> PathBuf {
+    PathBuf::from(DEFAULT_FORGE)
+}
+
+impl Config {
+    pub fn load() -> Result<Config> {
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        let mut path = None;
+        let mut keys = vec![];
+        let mut cache = None;
+        let mut snapshot = None;
+        let mut save_snapshot = None;
+        let mut record = None;
+        let mut strategy = None;
+        let mut replay = None;
+        let mut history = None;
+        let mut record_history = None;
+        let mut all_recipes = false;
+        let mut it = args.iter();
+        while let Some(arg) = it.next() {
+            match arg.as_str() {
+                "--config" => path = Some(PathBuf::from(flag_value(&mut it, arg)?)),
+                "--key" => keys.push(flag_value(&mut it, arg)?),
+                "--cache" => cache = Some(PathBuf::from(flag_value(&mut it, arg)?)),
+                "--snapshot" => snapshot = Some(PathBuf::from(flag_value(&mut it, arg)?)),
+                "--save-snapshot" => save_snapshot = Some(PathBuf::from(flag_value(&mut it, arg)?)),
+                "--record" => record = Some(PathBuf::from(flag_value(&mut it, arg)?)),
+                "--replay" => replay = Some(PathBuf::from(flag_value(&mut it, arg)?)),
+                "--history" => history = Some(PathBuf::from(flag_value(&mut it, arg)?)),
+                "--all-recipes" => all_recipes = true,
+                "--record-history" => record_history = Some(flag_value(&mut it, arg)?.parse::<u64>()?),
+                "--strategy" => {
+                    let value = flag_value(&mut it, arg)?;
+                    let mut parts = value.splitn(2, '/');
+                    match Strategy::parse(parts.next().unwrap_or(""), parts.next().unwrap_or("")) {
+                        Some(s) => strategy = Some(s),
+                        None => return Err(Error::Usage(format!("--strategy takes <instant|order>/<instant|order>, not {:?}", value))),
+                    }
+                }
+                _ => return Err(Error::Usage(format!("unknown argument {:?}", arg))),
+            }
+        }
+
+        let mut config = match path {
+            Some(p) => Config::read(&p)?,
+            None if Path::new(DEFAULT_PATH).exists() => Config::read(Path::new(DEFAULT_PATH))?,
+            None => Config::default(),
+        };
+        // An empty variable counts as unset.
+        let env_keys: Vec<String> = std::env::var(KEY_VAR).unwrap_or_default()
+            .split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect();
+        if !env_keys.is_empty() {
+            config.key = None;
+            config.keys = env_keys;
+        }
+        if !keys.is_empty() {
+            config.key = None;
+            config.keys = keys;
+        }
+        if let Some(c) = cache {
+            config.cache = c;
+        }
+        if let Some(h) = history {
+            config.history = h;
+        }
+        config.all_recipes |= all_recipes;
+        config.record_history = record_history;
+        config.snapshot = snapshot;
+        config.save_snapshot = save_snapshot;
+        if record.is_some() && replay.is_some() {
+            return Err(Error::Usage("--record and --replay are exclusive".into()));
+        }
+        config.record = record;
+        if let Some(s) = strategy {
+            config.strategy = s;
+        }
+        config.replay = replay;
+        Ok(config)
+    }
+
+    /// The configured keys, one per account.
+    pub fn all_keys(&self) -> Vec<String> {
+        self.key.iter().chain(self.keys.iter()).cloned().collect()
+    }
+
+    fn read(path: &Path) -> Result<Config> {
+        let file = std::fs::File::open(path)?;
+        error::from_json(&path.display().to_string(), std::io::BufReader::new(file))
+    }
+}
+
+fn flag_value<'a>(it: &mut impl Iterator<Item=&'a String>, flag: &str) -> Result<String> {
+    match it.next() {
+        Some(v) => Ok(v.clone()),
+        None => Err(Error::Usage(format!("{} needs a value", flag))),
+    }
+}
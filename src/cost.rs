@@ -1,32 +1,102 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
-use crate::client::{ItemId, RecipeId};
+use crate::client::{ItemId, Recipe, RecipeId};
+use crate::error::Error;
+use crate::forge;
 use crate::index::Index;
+use crate::money::money;
+use crate::pricing::Strategy;
 
 #[derive(Debug, Clone)]
 pub enum Source {
     Vendor,
     Recipe {
         id: RecipeId,
-        ingredients: HashMap<ItemId, Cost>,
+        /// In the order the recipe lists them.
+        ingredients: Vec<Cost>,
+    },
+    /// Made with a Mystic Forge recipe (see `forge`).
+    Forge {
+        id: RecipeId,
+        ingredients: Vec<Cost>,
     },
     Auction,
-    Unknown,
+    Unknown(Unknown),
     Special,
+    /// Bought from a vendor with currencies other than coin, and maybe some
+    /// copper too.
+    Currency { copper: i32, amounts: Vec<(String, i32)> },
     Bank {
         used: i32,
         rest: Option<Box<Source>>,
     },
+    /// Crafted up to a limit (e.g. a daily-gated craft), and the rest bought.
+    Split {
+        crafted: Box<Cost>,
+        bought: Box<Cost>,
+    },
+}
+
+/// Why an item has no known cost.
+#[derive(Debug, Clone)]
+pub enum Unknown {
+    /// The API returned no trading post listings for the item.
+    NotListed,
+    /// There are listings, but not enough to cover the quantity.
+    Short { shortfall: i32 },
+    /// Listings for the item were never requested.
+    NotFetched,
+    /// The item's special valuation failed.
+    Rule(String),
 }
 
 impl Source {
+    /// Crafting `recipe` from `ingredients`, at a crafting station or the
+    /// forge as the recipe says.
+    pub fn craft(recipe: &Recipe, ingredients: Vec<Cost>) -> Source {
+        if forge::is_forge(recipe) {
+            Source::Forge { id: recipe.id, ingredients }
+        } else {
+            Source::Recipe { id: recipe.id, ingredients }
+        }
+    }
+
+    /// The recipe and ingredients, if this is crafted.
+    pub fn crafted(&self) -> Option<(RecipeId, &[Cost])> {
+        match self {
+            Source::Recipe { id, ingredients } | Source::Forge { id, ingredients } => Some((*id, ingredients)),
+            _ => None,
+        }
+    }
+
+    fn is_short(&self) -> bool {
+        if let Some((_, ingredients)) = self.crafted() {
+            return ingredients.iter().any(Cost::is_short);
+        }
+        match self {
+            Source::Unknown(Unknown::Short { .. }) => true,
+            Source::Bank { rest: Some(r), .. } => r.is_short(),
+            Source::Split { crafted, bought } => crafted.is_short() || bought.is_short(),
+            _ => false,
+        }
+    }
+
     pub fn to_str(&self) -> String {
         match *self {
             Source::Vendor => " [VENDOR]".into(),
             //Source::Recipe { .. } => " [RECIPE]".into(),
             //Source::Auction => " [AUCTION]".into(),
-            Source::Unknown => " [UNKNOWN]".into(),
+            Source::Unknown(Unknown::NotListed) => " [UNKNOWN: not listed]".into(),
+            Source::Unknown(Unknown::Short { shortfall }) => format!(" [UNKNOWN: market short {}]", shortfall),
+            Source::Unknown(Unknown::NotFetched) => " [UNKNOWN]".into(),
+            Source::Unknown(Unknown::Rule(ref e)) => format!(" [UNKNOWN: {}]", e),
             Source::Special => " [SPECIAL]".into(),
+            Source::Forge { .. } => " [FORGE]".into(),
+            Source::Currency { copper, ref amounts } => {
+                let coin = if copper > 0 { Some(money(copper)) } else { None };
+                let amounts: Vec<_> = coin.into_iter().chain(amounts.iter().map(|(c, n)| format!("{} {}", n, c))).collect();
+                format!(" [VENDOR: {}]", amounts.join(", "))
+            }
             Source::Bank { .. } => " [BANK]".into(),
             _ => "".into(),
         }
@@ -41,11 +111,11 @@ pub struct Cost {
     pub total: i32,
 }
 
-fn base_ingredients_aux(id: &ItemId, source: &Source, quantity: i32) -> HashMap<ItemId, i32> {
-    let mut out = HashMap::new();
+fn base_ingredients_aux(id: &ItemId, source: &Source, quantity: i32) -> BTreeMap<ItemId, i32> {
+    let mut out = BTreeMap::new();
     match source {
-        Source::Recipe { ingredients, .. } => {
-            for ing in ingredients.values() {
+        Source::Recipe { ingredients, .. } | Source::Forge { ingredients, .. } => {
+            for ing in ingredients {
                 for (id, count) in ing.base_ingredients() {
                     *out.entry(id).or_insert(0) += count;
                 }
@@ -57,6 +127,13 @@ fn base_ingredients_aux(id: &ItemId, source: &Source, quantity: i32) -> HashMap<
                 *out.entry(id).or_insert(0) += count;
             }
         },
+        Source::Split { crafted, bought } => {
+            for part in &[crafted, bought] {
+                for (id, count) in part.base_ingredients() {
+                    *out.entry(id).or_insert(0) += count;
+                }
+            }
+        }
         _ => { out.insert(*id, quantity); },
     }
 
@@ -64,15 +141,27 @@ fn base_ingredients_aux(id: &ItemId, source: &Source, quantity: i32) -> HashMap<
 }
 
 impl Cost {
-    pub fn base_ingredients(&self) -> HashMap<ItemId, i32> {
+    pub fn base_ingredients(&self) -> BTreeMap<ItemId, i32> {
         base_ingredients_aux(&self.id, &self.source, self.quantity)
     }
 
-    pub fn new(index: &Index, id: &ItemId, quantity: i32) -> Cost {
-        Cost::new_with_bank(index, id, quantity, &mut HashMap::new())
+    /// Whether any part of this ran out of listings, so `total` is too low.
+    pub fn is_short(&self) -> bool {
+        self.source.is_short()
+    }
+
+    pub fn new(index: &Index, strategy: &Strategy, id: &ItemId, quantity: i32) -> Cost {
+        Cost::new_with_bank(index, strategy, id, quantity, &mut HashMap::new())
     }
 
-    pub fn new_with_bank(index: &Index, id: &ItemId, quantity: i32, bank: &mut HashMap<ItemId, i32>) -> Cost {
+    pub fn new_with_bank(index: &Index, strategy: &Strategy, id: &ItemId, quantity: i32, bank: &mut HashMap<ItemId, i32>) -> Cost {
+        Cost::new_aux(index, strategy, id, quantity, bank, &mut HashSet::new())
+    }
+
+    // `visiting` holds the items being crafted further up the tree; recipes
+    // that lead back to one of them (e.g. A from B from A) aren't followed,
+    // and the item is bought instead.
+    fn new_aux(index: &Index, strategy: &Strategy, id: &ItemId, quantity: i32, bank: &mut HashMap<ItemId, i32>, visiting: &mut HashSet<ItemId>) -> Cost {
         if let Some(count) = bank.get(id).cloned() {
             if count > 0 {
                 let used = std::cmp::min(quantity, count);
@@ -86,7 +175,7 @@ impl Cost {
                         total: 0,
                     }
                 } else {
-                    let rest = Cost::new_with_bank(index, id, remaining, bank);
+                    let rest = Cost::new_aux(index, strategy, id, remaining, bank, visiting);
                     Cost {
                         id: *id,
                         source: Source::Bank { used, rest: Some(Box::new(rest.source)) },
@@ -96,54 +185,61 @@ impl Cost {
                 }
             }
         }
-        if let Some(value) = vendor(id) {
-            return Cost {
-                id: *id,
-                source: Source::Vendor,
-                quantity,
-                total: quantity * value,
-            }
-        }
-        if let Some(value) = special(index, id) {
-            return Cost {
-                id: *id,
-                source: Source::Special,
-                quantity,
-                total: quantity * value,
-            }
+        if let Some(cost) = fixed(index, id, quantity) {
+            return cost;
         }
-        let recipe = match index.recipes_by_item.get(id) {
+        let recipes = match index.recipes_by_item.get(id).filter(|_| !visiting.contains(id)) {
             None => {
-                return index.listings.get(id)
-                    .and_then(|ls| ls.cost(quantity).ok())
-                    .map(|total| Cost {
+                let source = match index.listings.get(id).map(|ls| strategy.cost(ls, quantity)) {
+                    Some(Ok(total)) => return Cost {
                         id: *id,
                         source: Source::Auction,
                         quantity,
                         total,
-                    })
-                    .unwrap_or(Cost {
-                        id: *id,
-                        source: Source::Unknown,
-                        quantity,
-                        total: 0,
-                    })
+                    },
+                    Some(Err(Error::InsufficientDepth { shortfall, .. })) => Unknown::Short { shortfall },
+                    _ if index.missing.listings.contains(id) => Unknown::NotListed,
+                    _ => Unknown::NotFetched,
+                };
+                return Cost {
+                    id: *id,
+                    source: Source::Unknown(source),
+                    quantity,
+                    total: 0,
+                }
             }
-            Some(r) => r,
+            Some(rs) => rs,
         };
-        let runs = ((quantity as f32) / (recipe.output_item_count as f32)).ceil() as i32;
-        let mut craft_total = 0;
-        let mut ingredients = HashMap::new();
         // Snapshot the bank before computing crafted cost so it can be set back
         // to this if auctioning is cheaper.
         let old_bank = bank.clone();
-        for ing in &recipe.ingredients {
-            let ing_cost = Cost::new_with_bank(index, &ing.item_id, ing.count * runs, bank);
-            craft_total += ing_cost.total;
-            ingredients.insert(ing.item_id, ing_cost);
+        // Cost every recipe against its own copy of the bank, and keep the
+        // cheapest, leaving `bank` as it leaves it.
+        // A recipe whose ingredients run out of listings only looks cheap.
+        let mut best: Option<((bool, i32), &Recipe, Vec<Cost>)> = None;
+        visiting.insert(*id);
+        for recipe in recipes {
+            let mut recipe_bank = old_bank.clone();
+            // Random outputs are costed by their expected count.
+            let runs = recipe.runs(quantity);
+            let mut craft_total = 0;
+            let mut ingredients = vec![];
+            for ing in &recipe.ingredients {
+                let ing_cost = Cost::new_aux(index, strategy, &ing.item_id, ing.count * runs, &mut recipe_bank, visiting);
+                craft_total += ing_cost.total;
+                ingredients.push(ing_cost);
+            }
+            let rank = (ingredients.iter().any(Cost::is_short), craft_total);
+            if best.as_ref().is_none_or(|(r, ..)| rank < *r) {
+                best = Some((rank, recipe, ingredients));
+                *bank = recipe_bank;
+            }
         }
+        visiting.remove(id);
+        // `recipes_by_item` never holds an empty list.
+        let ((_, craft_total), recipe, ingredients) = best.unwrap();
         if let Some(ls) = index.listings.get(id) {
-            if let Ok(total) = ls.cost(quantity) {
+            if let Ok(total) = strategy.cost(ls, quantity) {
                 if total < craft_total {
                     *bank = old_bank;
                     return Cost {
@@ -157,83 +253,41 @@ impl Cost {
         }
         Cost {
             id: *id,
-            source: Source::Recipe {
-                id: recipe.id,
-                ingredients,
-            },
+            source: Source::craft(recipe, ingredients),
             quantity,
             total: craft_total,
         }
     }
 }
 
-fn vendor(id: &ItemId) -> Option<i32> {
-    Some(match id.0 {
-        // Thermocatalytic Reagent
-        46747 => 150,
-        // Spool of Gossamer Thread
-        19790 => 64,
-        // Spool of Silk Thread
-        19791 => 48,
-        // Spool of Linen Thread
-        19793 => 32,
-        // Spool of Cotton Thread
-        19794 => 24,
-        // Spool of Wool Thread
-        19789 => 16,
-        // Spool of Jute Thread
-        19792 => 8,
-        // Milling Basin
-        76839 => 56,
-        // Lump of Tin
-        19704 => 8,
-        // Lump of Coal
-        19750 => 16,
-        // Lump of Primordium
-        19924 => 48,
-        _ => return None,
+/// Cost of an item with a set price: sold by a vendor, or valued by a
+/// special rule.  A rule that can't be evaluated gives an unknown cost.
+pub fn fixed(index: &Index, id: &ItemId, quantity: i32) -> Option<Cost> {
+    if let (Some(value), Some(offer)) = (index.vendor.price(id), index.vendor.offer(id)) {
+        let source = if offer.currencies.is_empty() {
+            Source::Vendor
+        } else {
+            Source::Currency { copper: offer.coin(quantity), amounts: offer.amounts(quantity) }
+        };
+        return Some(Cost {
+            id: *id,
+            source,
+            quantity,
+            total: quantity * value,
+        })
+    }
+    Some(match index.special.value(index, id)? {
+        Ok(value) => Cost {
+            id: *id,
+            source: Source::Special,
+            quantity,
+            total: quantity * value,
+        },
+        Err(e) => Cost {
+            id: *id,
+            source: Source::Unknown(Unknown::Rule(e.to_string())),
+            quantity,
+            total: 0,
+        },
     })
 }
-
-pub fn special(index: &Index, id: &ItemId) -> Option<i32> {
-    Some(match id.0 {
-        // Obsidian Shard
-        // 5 for 1 Guild Commendation daily at the Guild Trader
-        // Guild Commendation ~= 50s
-        19925 => 1000,
-        // Charged Quartz Crystal
-        // 25 Quartz Crystals at a place of power daily
-        43772 => index.listings.get(&ItemId(43773)).unwrap().cost(25).unwrap(),
-        // Plaguedoctor's Orichalcum-Imbued Inscription
-        // 2500 Volatile Magic + 50 Inscribed Shard ~= 3500 Volatile Magic
-        // https://gw2lunchbox.com/IstanShipments.html puts VM at ~40s per 250 (Trophy Shipment)
-        // for ~16c per 1 Volatile Magic
-        // * 3500 = 56000
-        87809 => 2*56000,
-        // Plaguedoctor's Intricate Gossamer Insignia
-        // 1250 Volatile Magic + 25 Inscribed Shard ~= 1750 Volatile Magic
-        // ~= 28000c
-        88011 => 2*28000,
-        // Branded Mass: 20 Volatile Magic ~= 320c
-        89537 => 320,
-        // Exquisite Serpentite Jewel
-        // It's a hassle to get - dwarven catacombs puzzle area daily chest.
-        89696 => 100000,
-        // Bottle of Airship Oil
-        // Handwave
-        69434 => 1000,
-        // Pile of Auric Dust
-        // Handwave
-        69432 => 1000,
-        // Ley Line Spark
-        // Handwave
-        69392 => 1000,
-        // Celestial Orichalcum Imbued Inscription
-        // Account-bound, recipe only available via pact supply agents
-        43775 => 1000000,
-        // Dungeon widgets
-        _ if index.offerings.contains(id) => 1000000,
-
-        _ => return None,
-    })
-}
\ No newline at end of file
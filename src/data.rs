@@ -0,0 +1,19 @@
+//! Data files under `data/` (vendor prices, special valuations, forge
+//! recipes).  Each is built into the binary, and the built-in copy is used
+//! when the file isn't on disk, so the files only need to exist to be edited.
+
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+
+use crate::error::{self, Result};
+
+/// Reads and parses `path`, or `bundled` if there's no such file.
+pub fn load_or_bundled<T: DeserializeOwned>(path: &Path, bundled: &str) -> Result<T> {
+    if path.exists() {
+        let f = std::fs::File::open(path)?;
+        error::from_json(&path.display().to_string(), std::io::BufReader::new(f))
+    } else {
+        error::from_json(&format!("bundled {}", path.display()), bundled.as_bytes())
+    }
+}
@@ -1,23 +1,99 @@
-
-use std::error::Error;
 use std::fmt;
 
-pub type Result<T> = std::result::Result<T, Box<dyn Error>>;
+use reqwest::StatusCode;
+
+use crate::client::{ItemId, RecipeId};
+
+pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug)]
-pub struct Failed(pub String);
+pub enum Error {
+    /// The API answered with a status that isn't success and wasn't retried
+    /// (or still failed after retrying).
+    Http { path: String, status: StatusCode, text: String },
+    /// The request never got a response.
+    Request(reqwest::Error),
+    /// An authenticated endpoint was requested with no key configured.
+    NoKey { path: String },
+    /// The API rejected the key outright.
+    InvalidToken { path: String },
+    /// The key is valid but lacks a permission the endpoint needs.
+    InsufficientToken { path: String, text: String },
+    /// A response or file didn't match the expected shape; `at` is the
+    /// location within the document.
+    Json { path: String, at: String, source: serde_json::Error },
+    /// The trading post doesn't have enough listings to fill an order.
+    InsufficientDepth { item: ItemId, shortfall: i32 },
+    UnknownItem(ItemId),
+    UnknownRecipe(RecipeId),
+    UnknownAccount(String),
+    /// A special valuation rule is malformed or can't be evaluated; `item`
+    /// is the item it values, or `None` for the offerings rule.
+    Rule { item: Option<ItemId>, text: String },
+    /// An API call was needed but there are no clients, i.e. the index was
+    /// loaded from a snapshot.
+    Offline,
+    /// Bad command-line argument or interactive command.
+    Usage(String),
+    Io(std::io::Error),
+}
 
-impl fmt::Display for Failed {
+impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Failed({})", self.0)
+        use Error::*;
+        match self {
+            Http { path, status, text } => write!(f, "{}: {} {}", path, status, text),
+            Request(e) => write!(f, "request failed: {}", e),
+            NoKey { path } => write!(f, "{} requires an API key (use --key, GW2_API_KEY or \"key\" in the config file)", path),
+            InvalidToken { path } => write!(f, "{}: invalid API key", path),
+            InsufficientToken { path, text } => write!(f, "{}: API key lacks permission: {}", path, text),
+            Json { path, at, source } => write!(f, "{}: bad JSON at {}: {}", path, at, source),
+            InsufficientDepth { item, shortfall } => write!(f, "item {}: market short by {}", item.0, shortfall),
+            UnknownItem(id) => write!(f, "unknown item {}", id.0),
+            UnknownRecipe(id) => write!(f, "unknown recipe {}", id.0),
+            UnknownAccount(name) => write!(f, "unknown account {:?}", name),
+            Rule { item: Some(id), text } => write!(f, "rule for item {}: {}", id.0, text),
+            Rule { item: None, text } => write!(f, "offerings rule: {}", text),
+            Offline => write!(f, "no API clients; running from a snapshot?"),
+            Usage(s) => write!(f, "{}", s),
+            Io(e) => write!(f, "{}", e),
+        }
     }
 }
 
-impl Error for Failed {}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Request(e) => Some(e),
+            Error::Json { source, .. } => Some(source),
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
 
-#[macro_export]
-macro_rules! failed {
-    ($($arg:expr),*) => {
-        return Err(Box::new(crate::error::Failed(format!($($arg),*))))
-    };
-}
\ No newline at end of file
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self { Error::Request(e) }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self { Error::Io(e) }
+}
+
+impl From<std::num::ParseIntError> for Error {
+    fn from(e: std::num::ParseIntError) -> Self { Error::Usage(e.to_string()) }
+}
+
+/// Decodes JSON, reporting where in the document decoding failed.
+pub fn from_json<T, R>(path: &str, reader: R) -> Result<T>
+where
+    T: serde::de::DeserializeOwned,
+    R: std::io::Read,
+{
+    let mut de = serde_json::Deserializer::from_reader(reader);
+    serde_path_to_error::deserialize(&mut de).map_err(|e| Error::Json {
+        path: path.to_string(),
+        at: e.path().to_string(),
+        source: e.into_inner(),
+    })
+}
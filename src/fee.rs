@@ -0,0 +1,30 @@
+//! Trading post fees.  Listing an item costs 5% of its price up front, and
+//! selling it costs another 10%; an instant sale into a buy order pays both
+//! at once.  Each fee is charged per unit, rounded to the nearest copper and
+//! at least 1c, so a stack's fees are not simply a percentage of its total.
+
+/// A run of units sold at the same price.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Fill {
+    pub unit_price: i32,
+    pub quantity: i32,
+}
+
+pub fn listing_fee(unit_price: i32) -> i32 {
+    percent(unit_price, 5)
+}
+
+pub fn exchange_fee(unit_price: i32) -> i32 {
+    percent(unit_price, 10)
+}
+
+/// Total listing and exchange fees on a sale made up of `fills`.
+pub fn fees(fills: &[Fill]) -> (i32, i32) {
+    fills.iter().fold((0, 0), |(listing, exchange), f| {
+        (listing + f.quantity * listing_fee(f.unit_price), exchange + f.quantity * exchange_fee(f.unit_price))
+    })
+}
+
+fn percent(unit_price: i32, pct: i32) -> i32 {
+    std::cmp::max(1, (unit_price * pct + 50) / 100)
+}
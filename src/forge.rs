@@ -0,0 +1,121 @@
+//! Mystic Forge recipes, read from a data file (see `data/forge.json`) and
+//! turned into `Recipe`s so the index and cost engine treat them like any
+//! other.
+
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::client::{Ingredient, ItemId, Outcome, Recipe, RecipeId};
+use crate::data;
+use crate::error::{Error, Result};
+
+/// `Recipe::typ` of forge recipes.
+pub const FORGE: &str = "MysticForge";
+
+#[derive(Deserialize)]
+struct File {
+    recipes: Vec<Entry>,
+}
+
+#[derive(Deserialize)]
+struct Entry {
+    id: i32,
+    output_item_id: ItemId,
+    #[serde(default = "one")]
+    output_item_count: i32,
+    ingredients: Vec<Ingredient>,
+    #[serde(default)]
+    outcomes: Vec<Outcome>,
+}
+
+fn one() -> i32 { 1 }
+
+/// Reads the recipes in `path` (see `data`).
+pub fn load(path: &Path) -> Result<Vec<Recipe>> {
+    let name = path.display().to_string();
+    let file: File = data::load_or_bundled(path, include_str!("../data/forge.json"))?;
+    file.recipes.into_iter().map(|mut e| {
+        if e.id <= 0 || e.ingredients.is_empty() || e.ingredients.len() > 4 {
+            return Err(Error::Usage(format!("{}: forge recipe {} needs a positive id and 1 to 4 ingredients", name, e.id)));
+        }
+        if let Some(problem) = net_of_used(&mut e) {
+            return Err(Error::Usage(format!("{}: forge recipe {} {}", name, e.id, problem)));
+        }
+        if let Some(problem) = check_outcomes(&e) {
+            return Err(Error::Usage(format!("{}: forge recipe {} {}", name, e.id, problem)));
+        }
+        // For random recipes this is only a rough size for liquidity and
+        // daily limits; costs use the expected count.
+        let expected: f32 = e.outcomes.iter()
+            .filter(|o| o.item_id == e.output_item_id)
+            .map(|o| o.chance * o.count as f32)
+            .sum();
+        let output_item_count = if e.outcomes.is_empty() { e.output_item_count } else { std::cmp::max(1, expected.round() as i32) };
+        Ok(Recipe {
+            typ: FORGE.into(),
+            output_item_id: e.output_item_id,
+            output_item_count,
+            min_rating: 0,
+            time_to_craft_ms: 0,
+            disciplines: vec![FORGE.into()],
+            flags: vec![],
+            ingredients: e.ingredients,
+            id: RecipeId(-e.id),
+            chat_link: String::new(),
+            outcomes: e.outcomes,
+        })
+    }).collect()
+}
+
+// A recipe that uses its own output, like promoting materials (a T6 and 50
+// T5 make 5 to 12 T6), is turned into one that makes the net gain from the
+// other ingredients, since the unit put in comes back out.  That keeps the
+// cost engine from costing the item in terms of itself, and sells only what
+// was gained.
+fn net_of_used(e: &mut Entry) -> Option<&'static str> {
+    let output = e.output_item_id;
+    let used: i32 = e.ingredients.iter().filter(|i| i.item_id == output).map(|i| i.count).sum();
+    if used == 0 {
+        return None;
+    }
+    e.ingredients.retain(|i| i.item_id != output);
+    if e.ingredients.is_empty() {
+        return Some("needs an ingredient besides its output");
+    }
+    if e.outcomes.is_empty() {
+        if e.output_item_count <= used {
+            return Some("uses as much of its output as it makes");
+        }
+        e.output_item_count -= used;
+        return None;
+    }
+    if e.outcomes.iter().any(|o| o.item_id != output || o.count <= used) {
+        return Some("uses its output, so every outcome must give more of it than is used");
+    }
+    for o in &mut e.outcomes {
+        o.count -= used;
+    }
+    None
+}
+
+fn check_outcomes(e: &Entry) -> Option<&'static str> {
+    if e.outcomes.is_empty() {
+        return None;
+    }
+    if e.outcomes.iter().any(|o| o.count <= 0 || !(o.chance > 0.0 && o.chance <= 1.0)) {
+        return Some("has an outcome without a positive count and a chance in (0, 1]");
+    }
+    let total: f32 = e.outcomes.iter().map(|o| o.chance).sum();
+    if (total - 1.0).abs() > 0.001 {
+        return Some("has outcome chances that don't sum to 1");
+    }
+    if !e.outcomes.iter().any(|o| o.item_id == e.output_item_id) {
+        return Some("has no outcome giving its output_item_id");
+    }
+    None
+}
+
+pub fn is_forge(recipe: &Recipe) -> bool {
+    recipe.typ == FORGE
+}
@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use futures::future;
+use serde::{Deserialize, Serialize};
+
+use crate::client::{Client, ItemId, Listings, Price};
+use crate::error::{self, Result};
+use crate::liquidity::Liquidity;
+
+/// One item's trading post state at one time.  Snapshots are appended to the
+/// history file as JSON lines, so recording can be interrupted at any point
+/// and the file is never rewritten.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Snapshot {
+    /// Seconds since the Unix epoch.
+    pub time: u64,
+    pub id: ItemId,
+    /// Best buy order and lowest sell listing; 0 if there are none.
+    pub buy: i32,
+    pub sell: i32,
+    /// Total units wanted and offered.
+    pub demand: i32,
+    pub supply: i32,
+    /// Units wanted near the best buy order (see `Liquidity`).
+    pub depth: i32,
+}
+
+/// Low, high and mean of a price over a window.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Range {
+    pub min: i32,
+    pub max: i32,
+    pub mean: f32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Stats {
+    pub samples: usize,
+    /// `None` if no snapshot in the window had orders on that side.
+    pub buy: Option<Range>,
+    pub sell: Option<Range>,
+}
+
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// Fetches listings and prices for `ids` and turns them into snapshots
+/// stamped `time`.
+pub async fn snapshot(client: &Client, ids: &[ItemId], time: u64) -> Result<Vec<Snapshot>> {
+    let (ls, ps) = future::try_join(client.listings(ids), client.prices(ids)).await?;
+    let prices: HashMap<_, _> = ps.found.into_iter().map(|p| (p.id, p)).collect();
+    Ok(from_listings(&ls.found, &prices, time))
+}
+
+/// Snapshots of listings already fetched, stamped `time`.  Totals come from
+/// `prices` where present, and are summed from the listings otherwise.
+pub fn from_listings<'a>(listings: impl IntoIterator<Item=&'a Listings>, prices: &HashMap<ItemId, Price>, time: u64) -> Vec<Snapshot> {
+    listings.into_iter().map(|l| {
+        let price = prices.get(&l.id);
+        Snapshot {
+            time,
+            id: l.id,
+            buy: l.buys.first().map_or(0, |o| o.unit_price),
+            sell: l.sells.first().map_or(0, |o| o.unit_price),
+            demand: price.map_or_else(|| l.buys.iter().map(|o| o.quantity).sum(), |p| p.buys.quantity),
+            supply: price.map_or_else(|| l.sells.iter().map(|o| o.quantity).sum(), |p| p.sells.quantity),
+            depth: Liquidity::new(l, 1).depth,
+        }
+    }).collect()
+}
+
+pub fn append(path: &Path, snapshots: &[Snapshot]) -> Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let mut w = BufWriter::new(file);
+    for s in snapshots {
+        serde_json::to_writer(&mut w, s).map_err(std::io::Error::from)?;
+        w.write_all(b"\n")?;
+    }
+    w.flush()?;
+    Ok(())
+}
+
+/// Every recorded snapshot, by item, oldest first.
+#[derive(Debug, Default)]
+pub struct History {
+    snapshots: HashMap<ItemId, Vec<Snapshot>>,
+}
+
+impl History {
+    /// Reads the history file; a missing file is an empty history.
+    pub fn load(path: &Path) -> Result<History> {
+        let mut history = History::default();
+        if !path.exists() {
+            return Ok(history);
+        }
+        let file = std::fs::File::open(path)?;
+        for (n, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() { continue }
+            let s: Snapshot = error::from_json(&format!("{}:{}", path.display(), n + 1), line.as_bytes())?;
+            history.snapshots.entry(s.id).or_insert_with(Vec::new).push(s);
+        }
+        for v in history.snapshots.values_mut() {
+            v.sort_by_key(|s| s.time);
+        }
+        Ok(history)
+    }
+
+    /// Buy and sell price ranges for `id` over snapshots taken at or after
+    /// `since`, or `None` if there are none.  Snapshots with no orders on a
+    /// side are left out of that side's range.
+    pub fn stats(&self, id: &ItemId, since: u64) -> Option<Stats> {
+        let window: Vec<_> = self.snapshots.get(id)?.iter().filter(|s| s.time >= since).collect();
+        if window.is_empty() {
+            return None;
+        }
+        Some(Stats {
+            samples: window.len(),
+            buy: range(window.iter().map(|s| s.buy)),
+            sell: range(window.iter().map(|s| s.sell)),
+        })
+    }
+}
+
+fn range(prices: impl Iterator<Item=i32>) -> Option<Range> {
+    let prices: Vec<i32> = prices.filter(|p| *p > 0).collect();
+    let sum: i64 = prices.iter().map(|p| *p as i64).sum();
+    Some(Range {
+        min: *prices.iter().min()?,
+        max: *prices.iter().max()?,
+        mean: sum as f32 / prices.len() as f32,
+    })
+}
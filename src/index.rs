@@ -1,102 +1,177 @@
 use std::collections::{HashMap, HashSet};
-use std::io::Write;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
 
-use crate::client::{CharacterRecipes, Client, Item, ItemId, Recipe, RecipeId, Listings};
-use crate::error::Result;
+use futures::{future, TryStreamExt};
+use serde::{Deserialize, Serialize};
 
+use crate::cache::Cache;
+use crate::client::{CharacterRecipes, Client, Currency, CurrencyId, Item, ItemId, Recipe, RecipeId, Listings, MAX_PAGE_SIZE};
+use crate::error::{self, Error, Result};
+use crate::special::Special;
+use crate::vendor::Vendor;
+
+#[derive(Deserialize, Serialize)]
 pub struct Index {
     pub recipes: HashMap<RecipeId, Recipe>,
-    pub recipes_by_item: HashMap<ItemId, Recipe>,
+    /// Every recipe for each output item, by recipe id.
+    pub recipes_by_item: HashMap<ItemId, Vec<Recipe>>,
     pub items: HashMap<ItemId, Item>,
-    pub materials: HashMap<ItemId, i32>,  // item -> bank count
+    pub banks: Vec<Bank>,
     pub listings: HashMap<ItemId, Listings>,
     pub offerings: HashSet<ItemId>,
+    #[serde(default)]
+    pub currencies: HashMap<CurrencyId, Currency>,
+    /// Items that can only be made once a day, with the daily craft or map
+    /// chest id they were matched to by name.
+    #[serde(default)]
+    pub daily: HashMap<ItemId, String>,
+    #[serde(default)]
+    pub missing: Missing,
+    /// Set from the vendor and special data files after the index is built
+    /// or loaded.
+    #[serde(skip)]
+    pub vendor: Vendor,
+    #[serde(skip)]
+    pub special: Special,
+}
+
+/// Ids that were requested from the API but not returned.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Missing {
+    pub recipes: HashSet<RecipeId>,
+    pub items: HashSet<ItemId>,
+    /// Items with no trading post listings, usually because they're account
+    /// bound or otherwise untradeable.
+    pub listings: HashSet<ItemId>,
+}
+
+/// Material storage counts and wallet for one account.
+#[derive(Deserialize, Serialize)]
+pub struct Bank {
+    pub account: String,
+    pub materials: HashMap<ItemId, i32>,  // item -> bank count
+    /// Empty if the key lacks the wallet permission.
+    #[serde(default)]
+    pub wallet: HashMap<CurrencyId, i32>,
+    /// Daily crafts and map chests already done today; empty if the key lacks
+    /// the progression permission.
+    #[serde(default)]
+    pub dailies_done: HashSet<String>,
 }
 
-#[allow(dead_code)]
 pub enum RecipeSource {
+    /// Recipes known to the accounts' characters.
     Characters,
+    /// Every recipe in the game.
     All,
 }
 
 impl Index {
-    pub fn new(client: &mut Client, source: RecipeSource) -> Result<Index> {
-        let all_ids;
+    /// Builds the index using one client per account; public endpoints are
+    /// fetched through the first client.  Recipes and items are taken from
+    /// `cache` where present, and newly fetched ones are added to it.
+    /// `forge` recipes are added alongside those from the API.
+    pub async fn new(clients: &[Client], source: RecipeSource, forge: &[Recipe], cache: &mut Cache) -> Result<Index> {
+        if clients.is_empty() {
+            return Err(Error::Offline);
+        }
+        let client = &clients[0];
+        let all_ids: Vec<RecipeId>;
         match source {
             RecipeSource::Characters => {
-                let names: Vec<String> = client.characters()?;
-                println!("{:?}", names);
                 let mut id_set = HashSet::<RecipeId>::new();
-                for name in &names {
-                    let r: CharacterRecipes = client.character_recipes(name)?;
-                    println!("{}: {}", name, r.recipes.len());
-                    for id in &r.recipes {
-                        id_set.insert(*id);
+                for client in clients {
+                    let names: Vec<String> = client.characters().await?;
+                    println!("{:?}", names);
+                    let rs: Vec<CharacterRecipes> = future::try_join_all(names.iter().map(|n| client.character_recipes(n))).await?;
+                    for (name, r) in names.iter().zip(rs) {
+                        println!("{}: {}", name, r.recipes.len());
+                        for id in &r.recipes {
+                            id_set.insert(*id);
+                        }
                     }
                 }
                 all_ids = id_set.iter().cloned().collect();
             }
             RecipeSource::All => {
-                all_ids = client.all_recipes()?;
+                // Walking the pages fetches every recipe whole, so they're
+                // all cached without a separate request by id.
+                let pages: Vec<Vec<Recipe>> = client.pages(false, "recipes", MAX_PAGE_SIZE).try_collect().await?;
+                all_ids = pages.iter().flatten().map(|r| r.id).collect();
+                cache.recipes.extend(pages.into_iter().flatten().map(|r| (r.id, r)));
             }
         }
         println!("known recipes: {}", all_ids.len());
 
+        let fetch_ids: Vec<RecipeId> = all_ids.iter().filter(|id| !cache.recipes.contains_key(id)).cloned().collect();
+        let rs = client.recipes(&fetch_ids).await?;
+        println!("fetched recipes: {} ({} missing)", rs.found.len(), rs.missing.len());
+        let mut missing = Missing::default();
+        missing.recipes.extend(rs.missing);
+        for r in rs.found {
+            cache.recipes.insert(r.id, r);
+        }
+
         let mut recipes = HashMap::new();
         let mut recipes_by_item = HashMap::new();
-        for ids in all_ids.chunks(50) {
-            let rs: Vec<Recipe> = client.recipes(ids)?;
-            for r in rs {
-                recipes.insert(r.id, r.clone());
-                recipes_by_item.insert(r.output_item_id, r);
-            }
-            print!(".");
-            std::io::stdout().flush()?;
+        let known = all_ids.iter().filter_map(|id| cache.recipes.get(id));
+        for r in known.chain(forge) {
+            recipes.insert(r.id, r.clone());
+            recipes_by_item.entry(r.output_item_id).or_insert_with(Vec::new).push(r.clone());
+        }
+        for rs in recipes_by_item.values_mut() {
+            rs.sort_by_key(|r| r.id);
         }
-        println!("");
         println!("retrieved recipes: {}", recipes.len());
 
         let mut all_items = HashSet::<ItemId>::new();
         for (_, r) in &recipes {
             all_items.insert(r.output_item_id);
+            all_items.extend(r.outcomes.iter().map(|o| o.item_id));
             for i in &r.ingredients {
                 all_items.insert(i.item_id);
             }
         }
         println!("total items: {}", all_items.len());
- 
+
+        // Item metadata, listings and banks are independent of each other.
+        let id_vec: Vec<_> = all_items.iter().filter(|id| !cache.items.contains_key(id)).cloned().collect();
+        let pid_vec: Vec<ItemId> = all_items.iter().cloned().collect();
+        let (is, ls, (banks, currencies), dailies) = future::try_join4(
+            client.items(&id_vec),
+            client.listings(&pid_vec),
+            future::try_join(fetch_banks(clients), fetch_currencies(client)),
+            future::try_join(client.daily_crafting(), client.map_chests()),
+        ).await?;
+
+        println!("fetched items: {} ({} missing)", is.found.len(), is.missing.len());
+        missing.items.extend(is.missing);
+        for i in is.found {
+            cache.items.insert(i.id, i);
+        }
+        cache.save()?;
+
         let mut items = HashMap::new();
-        let id_vec: Vec<_> = all_items.iter().cloned().collect();
-        for ids in id_vec.chunks(50) {
-            let is = client.items(&ids)?;
-            for i in is {
-                items.insert(i.id, i);
+        for id in &all_items {
+            if let Some(i) = cache.items.get(id) {
+                items.insert(*id, i.clone());
             }
-            print!(".");
-            std::io::stdout().flush()?;
         }
-        println!("");
         println!("retrieved items: {}", items.len());
-        
-        let pid_vec: Vec<ItemId> = all_items.iter().cloned().collect();
+
         let mut listings = HashMap::new();
-        for ids in pid_vec.chunks(50) {
-            let ls = client.listings(ids)?;
-            for l in ls {
-                listings.insert(l.id, l);
-            }
-            print!(".");
-            std::io::stdout().flush()?;
+        for l in ls.found {
+            listings.insert(l.id, l);
         }
-        println!("");
-        println!("retrieve listings: {}", listings.len());
+        println!("retrieve listings: {} ({} missing)", listings.len(), ls.missing.len());
+        missing.listings.extend(ls.missing);
 
-        let mut materials = HashMap::new();
-        let ms = client.materials()?;
-        println!("materials: {}", ms.len());
-        for m in ms {
-            materials.insert(m.id, m.count);
-        }
+        let dailies: HashSet<String> = dailies.0.into_iter().chain(dailies.1).collect();
+        let daily: HashMap<ItemId, String> = items.values()
+            .filter_map(|i| Some((i.id, dailies.get(&slug(&i.name))?.clone())))
+            .collect();
+        println!("daily-gated items: {}", daily.len());
 
         let mut offerings = HashSet::new();
         for (id, item) in &items {
@@ -105,17 +180,134 @@ impl Index {
             }
         }
 
-        Ok(Index{recipes, recipes_by_item, items, materials, listings, offerings})
+        Ok(Index{recipes, recipes_by_item, items, banks, listings, offerings, currencies, daily, missing, vendor: Vendor::default(), special: Special::default()})
+    }
+
+    /// Reads an index previously written by `save`.
+    pub fn load(path: &Path) -> Result<Index> {
+        let file = std::fs::File::open(path)?;
+        error::from_json(&path.display().to_string(), BufReader::new(file))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let file = std::fs::File::create(path)?;
+        serde_json::to_writer(BufWriter::new(file), self).map_err(std::io::Error::from)?;
+        Ok(())
+    }
+
+    pub async fn refresh_materials(&mut self, clients: &[Client]) -> Result<()> {
+        if clients.is_empty() {
+            return Err(Error::Offline);
+        }
+        self.banks = fetch_banks(clients).await?;
+        Ok(())
+    }
+
+    /// Looks a currency up by its name in the API.
+    pub fn currency(&self, name: &str) -> Option<&Currency> {
+        self.currencies.values().find(|c| c.name == name)
+    }
+
+    /// Wallet balances for the named account, or summed over all accounts.
+    pub fn wallet(&self, account: Option<&str>) -> Result<HashMap<CurrencyId, i32>> {
+        let mut out = HashMap::new();
+        let mut found = false;
+        for bank in &self.banks {
+            if account.is_some_and(|a| a != bank.account) { continue }
+            found = true;
+            for (id, value) in &bank.wallet {
+                *out.entry(*id).or_insert(0) += value;
+            }
+        }
+        if let (Some(a), false) = (account, found) {
+            return Err(Error::UnknownAccount(a.to_string()));
+        }
+        Ok(out)
+    }
+
+    /// Daily-gated items already made today by the named account, or by
+    /// every account.
+    pub fn daily_done(&self, account: Option<&str>) -> Result<HashSet<ItemId>> {
+        let mut banks = self.banks.iter().filter(|b| account.is_none_or(|a| a == b.account)).peekable();
+        if banks.peek().is_none() {
+            return match account {
+                Some(a) => Err(Error::UnknownAccount(a.to_string())),
+                None => Ok(HashSet::new()),
+            };
+        }
+        let done: Vec<&HashSet<String>> = banks.map(|b| &b.dailies_done).collect();
+        Ok(self.daily.iter()
+            .filter(|(_, d)| done.iter().all(|set| set.contains(*d)))
+            .map(|(id, _)| *id)
+            .collect())
+    }
+
+    pub fn item(&self, id: &ItemId) -> Result<&Item> {
+        self.items.get(id).ok_or(Error::UnknownItem(*id))
     }
 
-    pub fn refresh_materials(&mut self, client: &mut Client) -> Result<()> {
+    pub fn recipe(&self, id: &RecipeId) -> Result<&Recipe> {
+        self.recipes.get(id).ok_or(Error::UnknownRecipe(*id))
+    }
+
+    /// Bank counts for the named account, or summed over all accounts.
+    pub fn materials(&self, account: Option<&str>) -> Result<HashMap<ItemId, i32>> {
+        let mut out = HashMap::new();
+        let mut found = false;
+        for bank in &self.banks {
+            if account.is_some_and(|a| a != bank.account) { continue }
+            found = true;
+            for (id, count) in &bank.materials {
+                *out.entry(*id).or_insert(0) += count;
+            }
+        }
+        if let (Some(a), false) = (account, found) {
+            return Err(Error::UnknownAccount(a.to_string()));
+        }
+        Ok(out)
+    }
+}
+
+async fn fetch_banks(clients: &[Client]) -> Result<Vec<Bank>> {
+    future::try_join_all(clients.iter().map(|client| async move {
+        let account = client.account().await?.name;
         let mut materials = HashMap::new();
-        let ms = client.materials()?;
-        println!("materials: {}", ms.len());
+        let ms = client.materials().await?;
+        println!("materials ({}): {}", account, ms.len());
         for m in ms {
             materials.insert(m.id, m.count);
         }
-        self.materials = materials;
-        Ok(())
+        let wallet = match client.wallet().await {
+            Ok(w) => w.into_iter().map(|e| (e.id, e.value)).collect(),
+            Err(Error::InsufficientToken { .. }) => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+        let dailies_done = match future::try_join(client.account_daily_crafting(), client.account_map_chests()).await {
+            Ok((crafts, chests)) => crafts.into_iter().chain(chests).collect(),
+            Err(Error::InsufficientToken { .. }) => HashSet::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Bank { account, materials, wallet, dailies_done })
+    })).await
+}
+
+/// Turns an item name into the form the API uses for daily ids:
+/// "Spool of Silk Weaving Thread" becomes "spool_of_silk_weaving_thread".
+fn slug(name: &str) -> String {
+    let mut out = String::new();
+    for c in name.chars() {
+        if c.is_alphanumeric() {
+            out.extend(c.to_lowercase());
+        } else if c != '\'' && !out.is_empty() && !out.ends_with('_') {
+            out.push('_');
+        }
     }
-}
\ No newline at end of file
+    out.trim_end_matches('_').to_string()
+}
+
+async fn fetch_currencies(client: &Client) -> Result<HashMap<CurrencyId, Currency>> {
+    let ids = client.all_currencies().await?;
+    let cs = client.currencies(&ids).await?;
+    println!("currencies: {}", cs.found.len());
+    Ok(cs.found.into_iter().map(|c| (c.id, c)).collect())
+}
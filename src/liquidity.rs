@@ -0,0 +1,38 @@
+use crate::client::Listings;
+
+// Buy orders within this fraction of the best one count towards depth.
+const NEAR_TOP: f32 = 0.05;
+// Depth, in crafts' worth of output, that scores 0.5.
+const HALF_DEPTH: f32 = 10.0;
+
+/// How readily an item's output sells.
+#[derive(Debug, Copy, Clone)]
+pub struct Liquidity {
+    /// Units wanted by buy orders near the best one.
+    pub depth: i32,
+    /// Gap between the best buy and lowest sell, as a fraction of the sell.
+    pub spread: f32,
+    /// 0 (won't sell) to 1 (sells at once).
+    pub score: f32,
+}
+
+impl Liquidity {
+    /// Scores `listings` for selling `quantity` units at a time.
+    pub fn new(listings: &Listings, quantity: i32) -> Liquidity {
+        let best_buy = listings.buys.first().map_or(0, |l| l.unit_price);
+        let floor = ((best_buy as f32) * (1.0 - NEAR_TOP)).ceil() as i32;
+        let depth = listings.buys.iter()
+            .take_while(|l| l.unit_price >= floor)
+            .map(|l| l.quantity)
+            .sum();
+        let spread = match listings.sells.first() {
+            Some(l) if best_buy > 0 => ((l.unit_price - best_buy) as f32 / l.unit_price as f32).max(0.0),
+            Some(_) => 1.0,
+            // Nobody is selling, so anything listed is the lowest offer.
+            None => 0.0,
+        };
+        let crafts = depth as f32 / std::cmp::max(1, quantity) as f32;
+        let score = crafts / (crafts + HALF_DEPTH) * (1.0 - spread.min(1.0));
+        Liquidity { depth, spread, score }
+    }
+}
@@ -1,27 +1,62 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::io::{Write, stdin};
+use std::sync::Arc;
 
-#[macro_use]
-mod error;
-
+mod batch;
+mod cache;
 mod client;
+mod config;
 mod cost;
+mod data;
+mod error;
+mod fee;
+mod forge;
+mod history;
 mod index;
+mod liquidity;
+mod money;
+mod outcome;
+mod pricing;
+mod solver;
+mod special;
+mod transport;
+mod vendor;
+
+#[cfg(test)]
+mod tests;
 
-use crate::client::{Client, ItemId, Recipe, RecipeId};
+use crate::cache::Cache;
+use crate::client::{Client, ItemId, Recipe, RecipeId, Side, TransactionState};
+use crate::config::Config;
 use crate::cost::{Cost, Source};
-use crate::error::Result;
+use crate::error::{Error, Result};
+use crate::history::History;
 use crate::index::{Index, RecipeSource};
+use crate::liquidity::Liquidity;
+use crate::money::money;
+use crate::outcome::Spread;
+use crate::pricing::{Sale, Strategy};
+use crate::special::Special;
+use crate::transport::{Http, Record, Replay, Transport};
+use crate::vendor::Vendor;
 
 #[derive(Debug, Clone)]
 struct Profit {
     id: RecipeId,
     days: i32,
-    sale: i32,
+    sale: Sale,
     value: i32,
-    daily: HashSet<ItemId>,
+    liquidity: Liquidity,
+    daily: BTreeSet<ItemId>,
+    /// Daily-gated items in `daily` already made today.
+    daily_done: BTreeSet<ItemId>,
     cost: Cost,
+    /// `cost` was planned greedily (see `solver::Solution`).
+    greedy: bool,
     mats_profit: Option<i32>,
+    /// Spread of `value` when the output is random; `value` is then the
+    /// expected profit.
+    outcomes: Option<Spread>,
 }
 
 impl Profit {
@@ -29,57 +64,178 @@ impl Profit {
         let d = std::cmp::max(1, self.days) as f32;
         ((self.value as f32) / d).floor() as i32
     }
+
+    /// Profit per day discounted by how hard the output is to sell; profits
+    /// are ranked and filtered by this.
+    fn rank(&self) -> i32 {
+        ((self.per_day() as f32) * self.liquidity.score).floor() as i32
+    }
 }
 
-const MIN_PROFIT: i32 = 5000;
+// Default threshold on `Profit::rank`.
+const MIN_RANK: i32 = 5000;
+// Window of price history shown with a profit.
+const HISTORY_DAYS: u64 = 7;
+// Crafts evaluated in a batch by default.
+const MAX_BATCH: i32 = 50;
 
 enum Command {
     Done,
     Continue,
-    Print { min_profit: i32 },
+    Print { min_rank: i32 },
     RefreshMats,
     Profit { id: ItemId },
     Cost { id: ItemId, count: i32 },
+    Batch { id: ItemId, max: i32 },
+    Account { name: Option<String> },
+    Strategy { strategy: Strategy },
+    Trades { state: TransactionState },
+    Gems,
+    Wallet,
 }
 
-fn main() -> Result<()> {
-    let mut client = Client::new();
-    let mut index = Index::new(&mut client, RecipeSource::Characters)?;
+#[tokio::main]
+async fn main() -> Result<()> {
+    let config = Config::load()?;
+    let mut clients: Vec<Client> = vec![];
+    let mut index = if let Some(path) = &config.snapshot {
+        println!("loading snapshot {}", path.display());
+        Index::load(path)?
+    } else {
+        let transport: Arc<dyn Transport> = match (&config.record, &config.replay) {
+            (Some(dir), _) => Arc::new(Record::new(Box::new(Http::new(config.retry.timeout())?), dir)),
+            (_, Some(dir)) => Arc::new(Replay::new(dir)),
+            _ => Arc::new(Http::new(config.retry.timeout())?),
+        };
+        let base = Client::new(transport, None, config.retry.clone());
+        clients = config.all_keys().into_iter().map(|k| base.with_key(Some(k))).collect();
+        if clients.is_empty() {
+            clients.push(base);
+        }
+        for client in &clients {
+            check_key(client).await?;
+        }
+        let build = clients[0].build().await?.id;
+        let mut cache = Cache::load(&config.cache, build)?;
+        let forge = forge::load(&config.forge)?;
+        let source = if config.all_recipes { RecipeSource::All } else { RecipeSource::Characters };
+        Index::new(&clients, source, &forge, &mut cache).await?
+    };
+    index.vendor = Vendor::load(&config.vendor)?;
+    index.vendor.set_currency_values(&config.currency_values);
+    if !index.currencies.is_empty() {
+        for name in index.vendor.currency_names() {
+            if index.currency(name).is_none() {
+                println!("warning: vendor table names unknown currency {:?}", name);
+            }
+        }
+    }
+    index.special = Special::load(&config.special)?;
+    if let Some(path) = &config.save_snapshot {
+        index.save(path)?;
+        println!("saved snapshot {}", path.display());
+    }
+    if let Some(interval) = config.record_history {
+        return record_history(&index, &clients, &config.history, interval).await;
+    }
+    let history = History::load(&config.history)?;
 
-    let (flip_profits, bank_profits) = find_profits(&index);
+    // Account whose bank is used; `None` means all accounts combined.
+    let mut account: Option<String> = None;
+    let mut strategy = config.strategy;
+    let (mut flip_profits, mut bank_profits) = find_profits(&index, None, &strategy)?;
     println!("flip profits: {}", flip_profits.len());
     println!("bank profits: {}", bank_profits.len());
 
-    let mut command = Command::Print { min_profit: MIN_PROFIT };
+    let mut command = Command::Print { min_rank: MIN_RANK };
     loop {
         use Command::*;
         let mut print = None;
         match command {
             Done => break,
             Continue => (),
-            Print { min_profit } => print = Some(min_profit),
+            Print { min_rank } => print = Some(min_rank),
             RefreshMats => {
-                index.refresh_materials(&mut client)?;
-                print = Some(MIN_PROFIT);
+                // Daily limits and the bank both feed into profits.
+                let refreshed = index.refresh_materials(&clients).await
+                    .and_then(|()| find_profits(&index, account.as_deref(), &strategy));
+                match refreshed {
+                    Ok((fp, bp)) => {
+                        flip_profits = fp;
+                        bank_profits = bp;
+                        print = Some(MIN_RANK);
+                    }
+                    Err(e) => println!("Error: {}", e),
+                }
             }
             Profit { id } => {
                 for p in &flip_profits {
-                    let r = index.recipes.get(&p.id).unwrap();
+                    let r = index.recipe(&p.id)?;
                     if r.output_item_id == id {
-                        print_profit(&index, p)?;
+                        print_profit(&index, &history, account.as_deref(), &strategy, p)?;
+                    }
+                }
+            }
+            Cost { id, count } => {
+                let solution = solver::solve(&index, &strategy, &id, count, &mut HashMap::new(), &daily_limits(&index));
+                if solution.greedy {
+                    println!("{}", greedy_note());
+                }
+                if let Err(e) = print_cost(&index, &solution.cost, 0) {
+                    println!("Error: {}", e);
+                }
+            }
+            Batch { id, max } => {
+                if let Err(e) = print_batch(&index, &strategy, &id, max) {
+                    println!("Error: {}", e);
+                }
+            }
+            Account { name } => {
+                match find_profits(&index, name.as_deref(), &strategy) {
+                    Ok((fp, bp)) => {
+                        flip_profits = fp;
+                        bank_profits = bp;
+                        account = name;
+                        print = Some(MIN_RANK);
+                    }
+                    Err(e) => println!("Error: {}", e),
+                }
+            }
+            Strategy { strategy: s } => {
+                match find_profits(&index, account.as_deref(), &s) {
+                    Ok((fp, bp)) => {
+                        flip_profits = fp;
+                        bank_profits = bp;
+                        strategy = s;
+                        print = Some(MIN_RANK);
                     }
+                    Err(e) => println!("Error: {}", e),
+                }
+            }
+            Trades { state } => {
+                if let Err(e) = print_trades(&index, &clients, state).await {
+                    println!("Error: {}", e);
+                }
+            }
+            Gems => {
+                if let Err(e) = print_gems(&clients).await {
+                    println!("Error: {}", e);
+                }
+            }
+            Wallet => {
+                if let Err(e) = print_wallet(&index, account.as_deref()) {
+                    println!("Error: {}", e);
                 }
             }
-            Cost { id, count } => print_cost(&index, &cost::Cost::new(&index, &id, count), 0),
         };
-        if let Some(min_profit) = print {
+        if let Some(min_rank) = print {
             println!("");
-            println!("=== Flip Profits ===");
+            println!("=== Flip Profits ({:?} buy, {:?} sell) ===", strategy.buy, strategy.sell);
             println!("");
-            print_profits_min(&index, &flip_profits, min_profit)?;
-            println!("=== Bank Profits ===");
+            print_profits_min(&index, &history, account.as_deref(), &strategy, &flip_profits, min_rank)?;
+            println!("=== Bank Profits ({}) ===", account.as_deref().unwrap_or("all accounts"));
             println!("");
-            print_profits_min(&index, &bank_profits, min_profit)?;
+            print_profits_min(&index, &history, account.as_deref(), &strategy, &bank_profits, min_rank)?;
         }
         command = match get_command() {
             Ok(c) => c,
@@ -93,7 +249,51 @@ fn main() -> Result<()> {
     Ok(())
 }
 
-fn find_profits(index: &Index) -> (Vec<Profit>, Vec<Profit>) {
+/// Appends a snapshot of every indexed item's prices to `path` every
+/// `interval` seconds, or just once if `interval` is 0.  The first snapshot
+/// is of the listings the index was built with.
+async fn record_history(index: &Index, clients: &[Client], path: &std::path::Path, interval: u64) -> Result<()> {
+    let client = clients.first().ok_or(Error::Offline)?;
+    let ids: Vec<ItemId> = index.items.keys().cloned().collect();
+    let mut snapshots = history::from_listings(index.listings.values(), &HashMap::new(), history::now());
+    loop {
+        history::append(path, &snapshots)?;
+        println!("history: recorded {} items to {}", snapshots.len(), path.display());
+        if interval == 0 {
+            return Ok(());
+        }
+        // A failed snapshot is skipped rather than ending the recording.
+        loop {
+            tokio::time::delay_for(std::time::Duration::from_secs(interval)).await;
+            match history::snapshot(client, &ids, history::now()).await {
+                Ok(s) => {
+                    snapshots = s;
+                    break;
+                }
+                Err(e) => println!("history: snapshot failed, retrying in {}s: {}", interval, e),
+            }
+        }
+    }
+}
+
+// Permissions needed for the character recipe, material, wallet and daily
+// lookups.
+const NEEDED_PERMISSIONS: &[&str] = &["account", "characters", "inventories", "progression", "unlocks", "wallet"];
+
+async fn check_key(client: &Client) -> Result<()> {
+    let info = client.tokeninfo().await?;
+    println!("key {:?}: {}", info.name, info.permissions.join(", "));
+    for p in NEEDED_PERMISSIONS {
+        if !info.permissions.iter().any(|ip| ip == p) {
+            println!("\twarning: key lacks {:?} permission", p);
+        }
+    }
+    Ok(())
+}
+
+fn find_profits(index: &Index, account: Option<&str>, strategy: &Strategy) -> Result<(Vec<Profit>, Vec<Profit>)> {
+    let materials = index.materials(account)?;
+    let limits = daily_limits(index);
     let mut flip_profits = vec![];
     let mut bank_profits = vec![];
     for r in index.recipes.values() {
@@ -101,48 +301,70 @@ fn find_profits(index: &Index) -> (Vec<Profit>, Vec<Profit>) {
         if item.description.as_ref().map_or(false, |d| d.contains("used to craft the legendary")) { continue }
         if item.name == "Guild Catapult" { continue }
 
-        let sale = {
-            let listings = if let Some(ls) = index.listings.get(&r.output_item_id) { ls } else { continue };
-            let gross_sale = if let Ok(s) = listings.sale(r.output_item_count) { s } else { continue };
-            gross_sale - ((0.15 * (gross_sale as f32)).ceil()) as i32
+        let listings = if let Some(ls) = index.listings.get(&r.output_item_id) { ls } else { continue };
+        let runs = r.runs(1);
+        let (sale, spread) = if r.outcomes.is_empty() {
+            if let Ok(s) = strategy.sale(listings, r.output_item_count) { (s, None) } else { continue }
+        } else {
+            let payouts = if let Ok(ps) = outcome::payouts(index, strategy, r, runs) { ps } else { continue };
+            let (s, spread) = outcome::expected_sale(&payouts, runs);
+            (s, Some(spread))
         };
+        let liquidity = Liquidity::new(listings, r.output_item_count * runs);
 
-        if let Some(p) = flip_profit(index, r, sale) { flip_profits.push(p); }
-        if let Some(p) = bank_profit(index, r, sale) { bank_profits.push(p); }
+        // Profit differs from the sale by the same amount in every outcome.
+        let with_spread = |mut p: Profit| {
+            p.outcomes = spread.map(|s| s.less(sale.net() - p.value));
+            p
+        };
+        if let Some(p) = flip_profit(index, strategy, &limits, r, sale, liquidity) { flip_profits.push(with_spread(p)); }
+        if let Some(p) = bank_profit(index, strategy, &limits, &materials, r, sale, liquidity) { bank_profits.push(with_spread(p)); }
     }
-    flip_profits.sort_by(|b, a| { a.per_day().cmp(&b.per_day()) });
-    bank_profits.sort_by(|b, a| { a.per_day().cmp(&b.per_day()) });
-    (flip_profits, bank_profits)
+    let done = index.daily_done(account)?;
+    for p in flip_profits.iter_mut().chain(bank_profits.iter_mut()) {
+        p.daily_done = p.daily.iter().filter(|id| done.contains(id)).cloned().collect();
+    }
+    // Ties go to the lower recipe id, so runs print the same way.
+    flip_profits.sort_by(|b, a| { a.rank().cmp(&b.rank()).then(b.id.cmp(&a.id)) });
+    bank_profits.sort_by(|b, a| { a.rank().cmp(&b.rank()).then(b.id.cmp(&a.id)) });
+    Ok((flip_profits, bank_profits))
 }
 
-fn flip_profit(index: &Index, r: &Recipe, sale: i32) -> Option<Profit> {
-    let cost = Cost::new(&index, &r.output_item_id, 1);
-    if let Source::Auction = cost.source { return None }
-    let daily = days(&cost);
+fn flip_profit(index: &Index, strategy: &Strategy, limits: &HashMap<ItemId, i32>, r: &Recipe, sale: Sale, liquidity: Liquidity) -> Option<Profit> {
+    let solver::Solution { cost, greedy } = solver::solve(index, strategy, &r.output_item_id, 1, &mut HashMap::new(), limits);
+    if made_otherwise(&cost, r) { return None }
+    // What the market is short of costs nothing, so the profit is made up.
+    if cost.is_short() { return None }
+    let daily = days(index, &cost);
     let mut days = 0;
     for d in daily.values() {
         days = std::cmp::max(days, *d);
     }
     
-    if sale > cost.total {
+    if sale.net() > cost.total {
         return Some(Profit {
             id: r.id,
             days,
             sale,
-            value: sale - cost.total,
+            value: sale.net() - cost.total,
+            liquidity,
             daily: daily.keys().cloned().collect(),
+            daily_done: BTreeSet::new(),
             cost,
+            greedy,
             mats_profit: None,
+            outcomes: None,
         });
     }
     None
 }
 
-fn bank_profit(index: &Index, r: &Recipe, sale: i32) -> Option<Profit> {
-    let mut bank = index.materials.clone();
-    let cost = Cost::new_with_bank(&index, &r.output_item_id, 1, &mut bank);
-    if let Source::Auction = cost.source { return None }
-    let daily = days(&cost);
+fn bank_profit(index: &Index, strategy: &Strategy, limits: &HashMap<ItemId, i32>, materials: &HashMap<ItemId, i32>, r: &Recipe, sale: Sale, liquidity: Liquidity) -> Option<Profit> {
+    let mut bank = materials.clone();
+    let solver::Solution { cost, greedy } = solver::solve(index, strategy, &r.output_item_id, 1, &mut bank, limits);
+    if made_otherwise(&cost, r) { return None }
+    if cost.is_short() { return None }
+    let daily = days(index, &cost);
     let mut days = 0;
     for d in daily.values() {
         days = std::cmp::max(days, *d);
@@ -151,26 +373,40 @@ fn bank_profit(index: &Index, r: &Recipe, sale: i32) -> Option<Profit> {
     let used = bank_used(&cost);
     let mut used_profit = 0;
     for (id, count) in &used {
-        if let Some(s) = index.listings.get(id).and_then(|l| l.sale(*count).ok()) {
-            used_profit += s;
-        }/* else if let Some(sc) = cost::special(index, id) {
+        // Banked materials are valued at what selling them would net.
+        if let Some(s) = index.listings.get(id).and_then(|l| strategy.sale(l, *count).ok()) {
+            used_profit += s.net();
+        }/* else if let Some(Ok(sc)) = index.special.value(index, id) {
             used_profit += sc*count;
         }*/
     }
-    if sale > cost.total + used_profit {
+    if sale.net() > cost.total + used_profit {
         return Some(Profit {
             id: r.id,
             days,
             sale,
-            value: sale - (cost.total + used_profit),
+            value: sale.net() - (cost.total + used_profit),
+            liquidity,
             daily: daily.keys().cloned().collect(),
+            daily_done: BTreeSet::new(),
             cost,
+            greedy,
             mats_profit: Some(used_profit),
+            outcomes: None,
         });
     }
     None
 }
 
+// Whether `cost` buys some of the output or crafts it with a recipe other
+// than `r`.
+fn made_otherwise(cost: &Cost, r: &Recipe) -> bool {
+    match cost.source.crafted() {
+        Some((id, _)) => id != r.id,
+        None => matches!(cost.source, Source::Auction | Source::Split { .. }),
+    }
+}
+
 fn bank_used(c: &Cost) -> HashMap<ItemId, i32> {
     let mut out = HashMap::new();
     bank_used_aux(&c.id, &c.source, &mut out);
@@ -185,56 +421,93 @@ fn bank_used_aux(id: &ItemId, s: &Source, out: &mut HashMap<ItemId, i32>) {
                 bank_used_aux(id, r, out);
             }
         },
-        Source::Recipe { ingredients, .. } => {
-            for (id, c) in ingredients {
-                bank_used_aux(id, &c.source, out);
+        Source::Recipe { ingredients, .. } | Source::Forge { ingredients, .. } => {
+            for c in ingredients {
+                bank_used_aux(&c.id, &c.source, out);
             }
         },
+        Source::Split { crafted, bought } => {
+            bank_used_aux(&crafted.id, &crafted.source, out);
+            bank_used_aux(&bought.id, &bought.source, out);
+        }
         _ => ()
     }
 }
 
-fn print_profits_min(index: &Index, profits: &[Profit], min: i32) -> Result<()> {
+fn print_profits_min(index: &Index, history: &History, account: Option<&str>, strategy: &Strategy, profits: &[Profit], min: i32) -> Result<()> {
     let mut daily_used = HashSet::new();
     'profits: for p in profits {
-        if p.per_day() < min { break }
-        let recipe = index.recipes.get(&p.id).unwrap();
-        let item = index.items.get(&recipe.output_item_id).unwrap();
+        if p.rank() < min { break }
+        let recipe = index.recipe(&p.id)?;
+        let item = index.item(&recipe.output_item_id)?;
+        let summary = format!("{} : {} per day, rank {}", item.name, money(p.per_day()), money(p.rank()));
         if p.days > 1 {
-            println!("(skip: {} : {} [{} days])\n", item.name, money(p.per_day()), p.days);
+            println!("(skip: {} [{} days])\n", summary, p.days);
+            continue
+        }
+        if !p.daily_done.is_empty() {
+            let names: Vec<_> = p.daily_done.iter().map(|d| index.item(d).map(|i| i.name.as_str())).collect::<Result<_>>()?;
+            println!("(skip: {} [done today: {}])\n", summary, names.join(", "));
             continue
         }
         for d in &p.daily {
             if !daily_used.insert(d) {
-                let used = index.items.get(d).unwrap();
-                println!("(skip: {} : {} [{}])\n", item.name, money(p.per_day()), used.name);
+                let used = index.item(d)?;
+                println!("(skip: {} [{}])\n", summary, used.name);
                 continue 'profits
             }
         }
-        print_profit(&index, &p)?;
+        print_profit(index, history, account, strategy, p)?;
         println!("");
     }
     Ok(())
 }
 
-fn print_profit(index: &Index, p: &Profit) -> Result<()> {
-    let recipe = index.recipes.get(&p.id).unwrap();
-    let item = index.items.get(&recipe.output_item_id).unwrap();
+fn print_profit(index: &Index, history: &History, account: Option<&str>, strategy: &Strategy, p: &Profit) -> Result<()> {
+    let recipe = index.recipe(&p.id)?;
+    let item = index.item(&recipe.output_item_id)?;
     let cost = &p.cost;
     println!("{} : {} ({} over {} days)", item.name, money(p.per_day()), money(p.value), p.days);
-    let output_price = index.listings.get(&item.id).unwrap().sale(1).unwrap();
-    println!("\tSale: {} = {} @ {}", money(p.sale), recipe.output_item_count, money(output_price));
+    println!("\tLiquidity: {:.2} ({} wanted near top, {:.0}% spread)", p.liquidity.score, p.liquidity.depth, p.liquidity.spread * 100.0);
+    for d in &p.daily_done {
+        println!("\tUnavailable until reset: {} already made today", index.item(d)?.name);
+    }
+    if let Some(spread) = &p.outcomes {
+        println!("\tSale: {} expected = {} - {} listing - {} exchange", money(p.sale.net()), money(p.sale.gross),
+            money(p.sale.listing_fee), money(p.sale.exchange_fee));
+        println!("\tOutcomes: best {}, worst {}, expected {} (deviation {})", money(spread.best), money(spread.worst),
+            money(spread.expected), money(spread.deviation()));
+        for o in outcome::payouts(index, strategy, recipe, recipe.runs(1))? {
+            println!("\t\t{:.1}% : {} {} = {}", o.chance * 100.0, o.count, index.item(&o.item_id)?.name, money(o.sale.net()));
+        }
+    } else {
+        let output_price = strategy.gross_sale(index.listings.get(&item.id).ok_or(Error::UnknownItem(item.id))?, 1)?;
+        println!("\tSale: {} = {} @ {} - {} listing - {} exchange", money(p.sale.net()), recipe.output_item_count, money(output_price),
+            money(p.sale.listing_fee), money(p.sale.exchange_fee));
+    }
+    if let Some(s) = history.stats(&item.id, history::now().saturating_sub(HISTORY_DAYS * 24 * 3600)) {
+        let range = |r: Option<history::Range>| r.map_or_else(|| "none".to_string(),
+            |r| format!("{}-{} (mean {})", money(r.min), money(r.max), money(r.mean.round() as i32)));
+        println!("\t{} days: buy {}, sell {} over {} snapshots", HISTORY_DAYS, range(s.buy), range(s.sell), s.samples);
+    }
     println!("\tCost: {}", money(cost.total));
+    if p.greedy {
+        println!("\t{}", greedy_note());
+    }
+    match batch::analyze(index, strategy, &daily_limits(index), recipe, MAX_BATCH) {
+        Ok(batch) if batch.crafts > 1 => println!("\tBatch: {} crafts for {}", batch.crafts, money(batch.profit)),
+        _ => (),
+    }
     if let Some(mp) = p.mats_profit {
         println!("\tMats: {}", money(mp));
     }
-    print_cost(&index, &cost, 1);
-    let ingredients = shopping_ingredients(&index, &cost);
+    print_cost(index, cost, 1)?;
+    let ingredients = shopping_ingredients(&index.materials(account)?, cost);
     let mut shop_cost = 0;
     println!("\tShopping:");
     for (id, count) in &ingredients {
-        let cost = Cost::new(&index, id, *count);
-        let item = index.items.get(id).unwrap();
+        let cost = Cost::new(index, strategy, id, *count);
+        let item = index.item(id)?;
         println!("\t\t{} : {} = {}{}", item.name, count, money(cost.total), cost.source.to_str());
         shop_cost += cost.total;
     }
@@ -242,8 +515,30 @@ fn print_profit(index: &Index, p: &Profit) -> Result<()> {
     Ok(())
 }
 
-fn print_cost(index: &Index, cost: &Cost, indent: usize) {
-    let ii = index.items.get(&cost.id).unwrap();
+fn print_batch(index: &Index, strategy: &Strategy, id: &ItemId, max: i32) -> Result<()> {
+    let item = index.item(id)?;
+    let recipe = match Cost::new(index, strategy, id, 1).source {
+        Source::Recipe { id, .. } | Source::Forge { id, .. } => index.recipe(&id)?,
+        _ => index.recipes_by_item.get(id).and_then(|rs| rs.first()).ok_or(Error::UnknownItem(*id))?,
+    };
+    let batch = batch::analyze(index, strategy, &daily_limits(index), recipe, max)?;
+    println!("{} : best {} crafts for {}", item.name, batch.crafts, money(batch.profit));
+    for (i, m) in batch.marginal.iter().enumerate() {
+        println!("\t{} : {}", i + 1, money(*m));
+    }
+    if (batch.marginal.len() as i32) < max {
+        println!("\t(market exhausted after {})", batch.marginal.len());
+    }
+    Ok(())
+}
+
+fn print_cost(index: &Index, cost: &Cost, indent: usize) -> Result<()> {
+    // Each part is shown as if it were the whole.
+    if let Source::Split { crafted, bought } = &cost.source {
+        print_cost(index, crafted, indent)?;
+        return print_cost(index, bought, indent);
+    }
+    let ii = index.item(&cost.id)?;
     let tabs: Vec<_> = std::iter::repeat("\t").take(indent).collect();
     let tabs = tabs.join("");
     let (quantity, total) = if let Source::Bank { used, .. } = cost.source {
@@ -251,25 +546,35 @@ fn print_cost(index: &Index, cost: &Cost, indent: usize) {
     } else {
         (cost.quantity, cost.total)
     };
-    println!("{}{} : {} = {}{}", tabs, ii.name, quantity, money(total), cost.source.to_str());
+    // Which of the item's recipes was used, by its place in `recipes_by_item`.
+    let alternatives = index.recipes_by_item.get(&cost.id).map_or(&[][..], |rs| &rs[..]);
+    let recipe = match cost.source.crafted() {
+        Some((id, _)) if alternatives.len() > 1 => {
+            let n = alternatives.iter().position(|r| r.id == id).map_or(0, |i| i + 1);
+            format!(" [recipe {} of {}]", n, alternatives.len())
+        }
+        _ => "".into(),
+    };
+    println!("{}{} : {} = {}{}{}", tabs, ii.name, quantity, money(total), cost.source.to_str(), recipe);
     match &cost.source {
-        Source::Recipe { ingredients, .. } => {
-            for ing in ingredients.values() {
-                print_cost(index, ing, indent+1);
+        Source::Recipe { ingredients, .. } | Source::Forge { ingredients, .. } => {
+            for ing in ingredients {
+                print_cost(index, ing, indent+1)?;
             }
         }
         Source::Bank { used, rest: Some(r) } => {
             let subcost = Cost { source: (**r).clone(), quantity: cost.quantity - used, ..*cost };
-            print_cost(index, &subcost, indent);
+            print_cost(index, &subcost, indent)?;
         }
         _ => ()
     }
+    Ok(())
 }
 
-fn shopping_ingredients(index: &Index, cost: &Cost) -> HashMap<ItemId, i32> {
-    let mut out = HashMap::new();
+fn shopping_ingredients(materials: &HashMap<ItemId, i32>, cost: &Cost) -> BTreeMap<ItemId, i32> {
+    let mut out = BTreeMap::new();
     for (id, count) in cost.base_ingredients() {
-        let has = index.materials.get(&id).cloned().unwrap_or(0);
+        let has = materials.get(&id).cloned().unwrap_or(0);
         if has < count {
             out.insert(id, count - has);
         }
@@ -277,44 +582,81 @@ fn shopping_ingredients(index: &Index, cost: &Cost) -> HashMap<ItemId, i32> {
     out
 }
 
-fn money(amount: i32) -> String {
-    let mut out = String::new();
-    if amount >= 10000 {
-        out.push_str(&format!("{}g ", amount / 10000));
+async fn print_trades(index: &Index, clients: &[Client], state: TransactionState) -> Result<()> {
+    if clients.is_empty() {
+        return Err(Error::Offline);
     }
-    if amount >= 100 {
-        out.push_str(&format!("{}s ", (amount / 100) % 100));
+    for client in clients {
+        println!("=== {} ===", client.account().await?.name);
+        for side in &[Side::Buys, Side::Sells] {
+            let ts = client.transactions(state, *side).await?;
+            println!("{:?}: {}", side, ts.len());
+            for t in &ts {
+                let name = index.items.get(&t.item_id).map_or_else(|| format!("#{}", t.item_id.0), |i| i.name.clone());
+                let when = t.purchased.as_ref().unwrap_or(&t.created);
+                println!("\t{} : {} @ {} ({})", name, t.quantity, money(t.price), when);
+            }
+        }
+        if state == TransactionState::Current {
+            let d = client.delivery().await?;
+            println!("Delivery: {}, {} items", money(d.coins), d.items.len());
+            for i in &d.items {
+                let name = index.items.get(&i.id).map_or_else(|| format!("#{}", i.id.0), |i| i.name.clone());
+                println!("\t{} : {}", name, i.count);
+            }
+        }
     }
-    out.push_str(&format!("{}c", amount % 100));
-    out
+    Ok(())
 }
 
-fn is_daily(id: &ItemId) -> bool {
-    match id.0 {
-        // Charged Quartz Crystal
-        43772 => true,
-        // Glob of Elder Spirit Residue
-        46744 => true,
-        // Lump of Mithrillium
-        46742 => true,
-        // Spool of Silk Weaving Thread
-        46740 => true,
-        // Spool of Thick Elonian Cord
-        46745 => true,
-        _ => false,
+async fn print_gems(clients: &[Client]) -> Result<()> {
+    let client = clients.first().ok_or(Error::Offline)?;
+    let buy = client.exchange_coins(100 * 10000).await?;
+    let sell = client.exchange_gems(100).await?;
+    println!("100g buys {} gems ({} per gem)", buy.quantity, money(buy.coins_per_gem));
+    println!("100 gems sell for {} ({} per gem)", money(sell.quantity), money(sell.coins_per_gem));
+    Ok(())
+}
+
+fn print_wallet(index: &Index, account: Option<&str>) -> Result<()> {
+    let mut wallet: Vec<_> = index.wallet(account)?.into_iter()
+        .filter_map(|(id, value)| index.currencies.get(&id).map(|c| (c, value)))
+        .collect();
+    wallet.sort_by_key(|(c, _)| c.order);
+    println!("=== Wallet ({}) ===", account.unwrap_or("all accounts"));
+    for (c, value) in wallet {
+        match index.vendor.currency_value(&c.name) {
+            Some(v) => println!("\t{} : {} ~= {}", c.name, value, money((v * value as f32).round() as i32)),
+            None => println!("\t{} : {}", c.name, value),
+        }
     }
+    Ok(())
+}
+
+// Shown with costs the solver couldn't search exhaustively.
+fn greedy_note() -> String {
+    format!("(planned one item at a time: more than {} ways to craft it, so it may not be the cheapest)", solver::MAX_PLANS)
+}
+
+// Daily-gated items can be crafted once a day.
+fn daily_limits(index: &Index) -> HashMap<ItemId, i32> {
+    index.daily.keys().map(|id| (*id, 1)).collect()
 }
 
-fn days(cost: &Cost) -> HashMap<ItemId, i32> {
-    if is_daily(&cost.id) {
+fn days(index: &Index, cost: &Cost) -> HashMap<ItemId, i32> {
+    // What's bought past an item's limit takes no extra days.
+    if let Source::Split { crafted, .. } = &cost.source {
+        return days(index, crafted);
+    }
+    if index.daily.contains_key(&cost.id) {
         let mut out = HashMap::new();
         out.insert(cost.id, cost.quantity);
         return out;
     }
-    let ingredients = if let Source::Recipe { ingredients, .. } = &cost.source { ingredients } else { return HashMap::new() };
+    let ingredients = if let Some((_, ingredients)) = cost.source.crafted() { ingredients } else { return HashMap::new() };
     let mut out = HashMap::new();
-    for ing in ingredients.values() {
-        for (id, count) in days(ing) {
+    for ing in ingredients {
+        for (id, count) in days(index, ing) {
             *out.entry(id).or_insert(0) += count;
         }
     }
@@ -324,7 +666,8 @@ fn days(cost: &Cost) -> HashMap<ItemId, i32> {
 fn get_command() -> Result<Command> {
     use Command::*;
     let mut line = String::new();
-    println!("mats | profit <id> | cost <id> [count] | min profit <copper>");
+    println!("mats | profit <id> | cost <id> [count] | min profit <rank: copper/day x liquidity> | account <name|all> | strategy <instant|order> <instant|order>");
+    println!("batch <id> [max] | orders | trades | gems | wallet");
     print!("> ");
     std::io::stdout().flush()?;
     line.clear();
@@ -332,6 +675,10 @@ fn get_command() -> Result<Command> {
     let line = line.trim();
     if line == "exit" { return Ok(Done); }
     if line == "mats" { return Ok(RefreshMats); }
+    if line == "orders" { return Ok(Trades { state: TransactionState::Current }); }
+    if line == "trades" { return Ok(Trades { state: TransactionState::History }); }
+    if line == "gems" { return Ok(Gems); }
+    if line == "wallet" { return Ok(Wallet); }
     if let Some(rest) = line.strip_prefix("profit ") {
         return Ok(Profit { id: ItemId(rest.parse::<i32>()?) })
     }
@@ -343,8 +690,27 @@ fn get_command() -> Result<Command> {
         } else { 1 };
         return Ok(Cost { id, count })
     }
+    if let Some(rest) = line.strip_prefix("batch ") {
+        let parts: Vec<_> = rest.split(' ').collect();
+        let id = ItemId(parts[0].parse::<i32>()?);
+        let max = if parts.len() == 2 {
+            parts[1].parse::<i32>()?
+        } else { MAX_BATCH };
+        return Ok(Batch { id, max })
+    }
+    if let Some(rest) = line.strip_prefix("account ") {
+        let name = if rest == "all" { None } else { Some(rest.to_string()) };
+        return Ok(Account { name })
+    }
+    if let Some(rest) = line.strip_prefix("strategy ") {
+        let parts: Vec<_> = rest.split(' ').collect();
+        return match (parts.len(), pricing::Strategy::parse(parts[0], parts.get(1).unwrap_or(&""))) {
+            (2, Some(strategy)) => Ok(Strategy { strategy }),
+            _ => Err(Error::Usage("strategy <buy: instant|order> <sell: instant|order>".into())),
+        }
+    }
     if let Some(rest) = line.strip_prefix("min profit ") {
-        return Ok(Print { min_profit: rest.parse::<i32>()? })
+        return Ok(Print { min_rank: rest.parse::<i32>()? })
     }
-    failed!("unknown command {:?}", line);
+    Err(Error::Usage(format!("unknown command {:?}", line)))
 }
\ No newline at end of file
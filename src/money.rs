@@ -0,0 +1,13 @@
+//! Formatting coin amounts as gold, silver and copper.
+
+pub fn money(amount: i32) -> String {
+    let mut out = String::new();
+    if amount >= 10000 {
+        out.push_str(&format!("{}g ", amount / 10000));
+    }
+    if amount >= 100 {
+        out.push_str(&format!("{}s ", (amount / 100) % 100));
+    }
+    out.push_str(&format!("{}c", amount % 100));
+    out
+}
@@ -0,0 +1,76 @@
+//! Recipes with random output (see `Recipe::outcomes`): what crafting one is
+//! expected to sell for, and how far that can swing.
+
+use crate::client::{ItemId, Recipe};
+use crate::error::{Error, Result};
+use crate::index::Index;
+use crate::pricing::{Sale, Strategy};
+
+/// Spread of a value over a recipe's outcomes.
+#[derive(Debug, Copy, Clone)]
+pub struct Spread {
+    pub expected: i32,
+    pub variance: f32,
+    pub best: i32,
+    pub worst: i32,
+}
+
+impl Spread {
+    pub fn deviation(&self) -> i32 {
+        self.variance.sqrt().round() as i32
+    }
+
+    /// The spread with `amount` taken off every outcome.
+    pub fn less(&self, amount: i32) -> Spread {
+        Spread {
+            expected: self.expected - amount,
+            variance: self.variance,
+            best: self.best - amount,
+            worst: self.worst - amount,
+        }
+    }
+}
+
+/// Selling the output of a batch of crafts that all had one outcome.
+#[derive(Debug, Clone)]
+pub struct Payout {
+    pub chance: f32,
+    pub item_id: ItemId,
+    pub count: i32,
+    pub sale: Sale,
+}
+
+/// Sale of each of `recipe`'s outcomes over `runs` crafts.
+pub fn payouts(index: &Index, strategy: &Strategy, recipe: &Recipe, runs: i32) -> Result<Vec<Payout>> {
+    recipe.outcomes.iter().map(|o| {
+        let listings = index.listings.get(&o.item_id).ok_or(Error::UnknownItem(o.item_id))?;
+        let count = o.count * runs;
+        Ok(Payout { chance: o.chance, item_id: o.item_id, count, sale: strategy.sale(listings, count)? })
+    }).collect()
+}
+
+/// Expected sale of `runs` crafts, fee by fee, and the spread of its net.
+pub fn expected_sale(payouts: &[Payout], runs: i32) -> (Sale, Spread) {
+    let mean = |f: fn(&Sale) -> i32| -> i32 {
+        payouts.iter().map(|p| p.chance * f(&p.sale) as f32).sum::<f32>().round() as i32
+    };
+    let sale = Sale {
+        gross: mean(|s| s.gross),
+        listing_fee: mean(|s| s.listing_fee),
+        exchange_fee: mean(|s| s.exchange_fee),
+    };
+    let expected = sale.net();
+    // Each payout assumes every craft came out the same way; crafts are
+    // independent, so the variance of their total is `runs` times smaller.
+    let variance = payouts.iter()
+        .map(|p| p.chance * ((p.sale.net() - expected) as f32).powi(2))
+        .sum::<f32>() / runs as f32;
+    let nets = payouts.iter().map(|p| p.sale.net());
+    let spread = Spread {
+        expected,
+        variance,
+        best: nets.clone().max().unwrap_or(0),
+        worst: nets.min().unwrap_or(0),
+    };
+    (sale, spread)
+}
@@ -0,0 +1,95 @@
+use serde::Deserialize;
+
+use crate::client::Listings;
+use crate::error::Result;
+use crate::fee::{self, Fill};
+
+/// How ingredients are bought.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Buy {
+    /// Take the cheapest sell listings.
+    Instant,
+    /// Place a buy order and wait for it to fill.
+    Order,
+}
+
+/// How outputs are sold.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Sell {
+    /// Fill the highest buy orders.
+    Instant,
+    /// List on the sell side and wait for a buyer.
+    Order,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+pub struct Strategy {
+    pub buy: Buy,
+    pub sell: Sell,
+}
+
+impl Default for Strategy {
+    fn default() -> Self {
+        Strategy { buy: Buy::Instant, sell: Sell::Instant }
+    }
+}
+
+/// Proceeds of a sale, broken down by fee.
+#[derive(Debug, Copy, Clone)]
+pub struct Sale {
+    pub gross: i32,
+    pub listing_fee: i32,
+    pub exchange_fee: i32,
+}
+
+impl Sale {
+    pub fn net(&self) -> i32 {
+        self.gross - self.listing_fee - self.exchange_fee
+    }
+}
+
+impl Strategy {
+    pub fn parse(buy: &str, sell: &str) -> Option<Strategy> {
+        let buy = match buy {
+            "instant" => Buy::Instant,
+            "order" => Buy::Order,
+            _ => return None,
+        };
+        let sell = match sell {
+            "instant" => Sell::Instant,
+            "order" => Sell::Order,
+            _ => return None,
+        };
+        Some(Strategy { buy, sell })
+    }
+
+    /// Cost of buying `quantity` of an item.
+    pub fn cost(&self, listings: &Listings, quantity: i32) -> Result<i32> {
+        match self.buy {
+            Buy::Instant => listings.cost(quantity),
+            Buy::Order => listings.order_cost(quantity),
+        }
+    }
+
+    /// Price received for `quantity` of an item, before fees.
+    pub fn gross_sale(&self, listings: &Listings, quantity: i32) -> Result<i32> {
+        match self.sell {
+            Sell::Instant => listings.sale(quantity),
+            Sell::Order => listings.order_sale(quantity),
+        }
+    }
+
+    /// Proceeds of selling `quantity` of an item, with fees charged per
+    /// unit at the price each unit sells for.
+    pub fn sale(&self, listings: &Listings, quantity: i32) -> Result<Sale> {
+        let fills = match self.sell {
+            Sell::Instant => listings.sale_fills(quantity)?,
+            Sell::Order => vec![Fill { unit_price: listings.order_sale_price(quantity)?, quantity }],
+        };
+        let gross = fills.iter().map(|f| f.unit_price * f.quantity).sum();
+        let (listing_fee, exchange_fee) = fee::fees(&fills);
+        Ok(Sale { gross, listing_fee, exchange_fee })
+    }
+}
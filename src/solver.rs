@@ -0,0 +1,252 @@
+//! Minimum-cost plans.  `Cost::new_with_bank` decides craft-or-buy one node
+//! at a time, so an early ingredient can use up banked materials or market
+//! depth a later one needed more.  Here every combination of choices for the
+//! craftable items in the tree is costed as a whole, with the bank and each
+//! item's listings shared across the tree, and the cheapest kept.  Trees with
+//! too many combinations are planned one item at a time instead, still
+//! sharing the bank and listings and keeping to the limits.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::client::{ItemId, Recipe};
+use crate::cost::{self, Cost, Source, Unknown};
+use crate::error::Error;
+use crate::index::Index;
+use crate::pricing::Strategy;
+
+/// Trees with more combinations than this are planned greedily instead.
+pub const MAX_PLANS: usize = 4096;
+
+#[derive(Copy, Clone)]
+enum Choice<'a> {
+    Buy,
+    Craft(&'a Recipe),
+}
+
+/// A plan from `solve`.
+#[derive(Debug, Clone)]
+pub struct Solution {
+    pub cost: Cost,
+    /// The tree had more than `MAX_PLANS` combinations, so the plan was
+    /// chosen one item at a time and may not be the cheapest.
+    pub greedy: bool,
+}
+
+/// The cheapest way to get `quantity` of `id`, drawing on `bank` and
+/// crafting no more of any item than `limits` allows (e.g. daily-gated
+/// crafts); past its limit, the rest of an item is bought.  Plans short of
+/// listings are only chosen if there's nothing else.  `bank` is left as the
+/// chosen plan leaves it.
+pub fn solve(
+    index: &Index,
+    strategy: &Strategy,
+    id: &ItemId,
+    quantity: i32,
+    bank: &mut HashMap<ItemId, i32>,
+    limits: &HashMap<ItemId, i32>,
+) -> Solution {
+    let mut items = vec![];
+    craftable(index, id, &mut items);
+    let options: Vec<Vec<Choice>> = items.iter().map(|id| {
+        let mut cs: Vec<Choice> = index.recipes_by_item[id].iter().map(Choice::Craft).collect();
+        if index.listings.contains_key(id) {
+            cs.push(Choice::Buy);
+        }
+        cs
+    }).collect();
+    let plans = options.iter().try_fold(1usize, |n, cs| n.checked_mul(cs.len()));
+    let base: &HashMap<ItemId, i32> = bank;
+    if plans.is_none_or(|n| n > MAX_PLANS) {
+        let mut walk = Walk { index, strategy, limits, bank: base, plan: None, state: State::default(), visiting: HashSet::new() };
+        let cost = walk.cost(id, quantity);
+        walk.state.apply(bank);
+        return Solution { cost, greedy: true };
+    }
+
+    // Count through every combination, one digit per craftable item.
+    let mut digits = vec![0; items.len()];
+    let mut best: Option<((bool, i32), Cost, State)> = None;
+    loop {
+        let plan = items.iter().zip(&options).zip(&digits).map(|((id, cs), d)| (*id, cs[*d])).collect();
+        let mut walk = Walk { index, strategy, limits, bank: base, plan: Some(plan), state: State::default(), visiting: HashSet::new() };
+        let cost = walk.cost(id, quantity);
+        let rank = (cost.is_short(), cost.total);
+        if best.as_ref().is_none_or(|(r, ..)| rank < *r) {
+            best = Some((rank, cost, walk.state));
+        }
+
+        let mut i = 0;
+        while i < digits.len() {
+            digits[i] += 1;
+            if digits[i] < options[i].len() { break }
+            digits[i] = 0;
+            i += 1;
+        }
+        if i == digits.len() { break }
+    }
+    let (_, cost, state) = best.unwrap();
+    state.apply(bank);
+    Solution { cost, greedy: false }
+}
+
+// Items under `id`, including itself, that have a recipe and so a choice to
+// make, in the order first reached.
+fn craftable(index: &Index, id: &ItemId, out: &mut Vec<ItemId>) {
+    if out.contains(id) || cost::fixed(index, id, 1).is_some() {
+        return;
+    }
+    let recipes = if let Some(rs) = index.recipes_by_item.get(id) { rs } else { return };
+    out.push(*id);
+    for r in recipes {
+        for ing in &r.ingredients {
+            craftable(index, &ing.item_id, out);
+        }
+    }
+}
+
+/// Costs one plan, tracking what it takes from the bank and the market.
+struct Walk<'a> {
+    index: &'a Index,
+    strategy: &'a Strategy,
+    limits: &'a HashMap<ItemId, i32>,
+    /// The bank before any plan; what a plan takes from it is in `state`.
+    bank: &'a HashMap<ItemId, i32>,
+    /// The choice for each craftable item; `None` tries every choice at each
+    /// item in turn and keeps the cheapest.
+    plan: Option<HashMap<ItemId, Choice<'a>>>,
+    state: State,
+    /// Items being crafted further up the tree, which are bought rather than
+    /// crafted again if a recipe leads back to them.
+    visiting: HashSet<ItemId>,
+}
+
+/// What a plan has taken so far.  Plans share the bank they start from, so
+/// each only tracks the few items it takes.
+#[derive(Clone, Default)]
+struct State {
+    /// Units taken from the bank so far.
+    banked: HashMap<ItemId, i32>,
+    /// Units bought so far, so later purchases go deeper into the listings.
+    bought: HashMap<ItemId, i32>,
+    /// Units crafted so far.
+    crafted: HashMap<ItemId, i32>,
+}
+
+impl State {
+    // Takes what the plan used out of `bank`.
+    fn apply(&self, bank: &mut HashMap<ItemId, i32>) {
+        for (id, used) in &self.banked {
+            if let Some(n) = bank.get_mut(id) {
+                *n -= used;
+            }
+        }
+    }
+}
+
+impl<'a> Walk<'a> {
+    fn cost(&mut self, id: &ItemId, quantity: i32) -> Cost {
+        let taken = self.state.banked.get(id).cloned().unwrap_or(0);
+        let banked = self.bank.get(id).cloned().unwrap_or(0) - taken;
+        if banked > 0 {
+            let used = std::cmp::min(quantity, banked);
+            self.state.banked.insert(*id, taken + used);
+            if used == quantity {
+                return Cost { id: *id, source: Source::Bank { used, rest: None }, quantity, total: 0 };
+            }
+            let rest = self.cost(id, quantity - used);
+            return Cost { id: *id, source: Source::Bank { used, rest: Some(Box::new(rest.source)) }, quantity, total: rest.total };
+        }
+        if let Some(cost) = cost::fixed(self.index, id, quantity) {
+            return cost;
+        }
+        let index = self.index;
+        let choice = match (&self.plan, index.recipes_by_item.get(id)) {
+            _ if self.visiting.contains(id) => Choice::Buy,
+            (Some(plan), _) => plan.get(id).cloned().unwrap_or(Choice::Buy),
+            (None, Some(recipes)) => return self.cheapest(id, quantity, recipes),
+            (None, None) => Choice::Buy,
+        };
+        self.take(id, quantity, choice)
+    }
+
+    // Tries every recipe for `id`, and buying it, from the same state, and
+    // keeps the cheapest.
+    fn cheapest(&mut self, id: &ItemId, quantity: i32, recipes: &'a [Recipe]) -> Cost {
+        let start = self.state.clone();
+        let buy = if self.index.listings.contains_key(id) { Some(Choice::Buy) } else { None };
+        let mut best: Option<((bool, i32), Cost, State)> = None;
+        for choice in recipes.iter().map(Choice::Craft).chain(buy) {
+            self.state = start.clone();
+            let cost = self.take(id, quantity, choice);
+            let rank = (cost.is_short(), cost.total);
+            if best.as_ref().is_none_or(|(r, ..)| rank < *r) {
+                best = Some((rank, cost, self.state.clone()));
+            }
+        }
+        // `recipes_by_item` never holds an empty list.
+        let (_, cost, state) = best.unwrap();
+        self.state = state;
+        cost
+    }
+
+    fn take(&mut self, id: &ItemId, quantity: i32, choice: Choice<'a>) -> Cost {
+        match choice {
+            Choice::Craft(recipe) => self.craft_some(id, quantity, recipe),
+            Choice::Buy => self.buy(id, quantity),
+        }
+    }
+
+    // Crafts as much of `quantity` as the item's limit leaves room for, and
+    // buys the rest.
+    fn craft_some(&mut self, id: &ItemId, quantity: i32, recipe: &'a Recipe) -> Cost {
+        let made = match self.limits.get(id) {
+            Some(limit) => {
+                let room = std::cmp::max(0, limit - self.state.crafted.get(id).cloned().unwrap_or(0));
+                std::cmp::min(quantity, room / recipe.output_item_count * recipe.output_item_count)
+            }
+            None => quantity,
+        };
+        if made == 0 {
+            return self.buy(id, quantity);
+        }
+        let crafted = self.craft(id, made, recipe);
+        if made == quantity {
+            return crafted;
+        }
+        let bought = self.buy(id, quantity - made);
+        let total = crafted.total + bought.total;
+        Cost { id: *id, source: Source::Split { crafted: Box::new(crafted), bought: Box::new(bought) }, quantity, total }
+    }
+
+    fn craft(&mut self, id: &ItemId, quantity: i32, recipe: &'a Recipe) -> Cost {
+        self.visiting.insert(*id);
+        let runs = recipe.runs(quantity);
+        *self.state.crafted.entry(*id).or_insert(0) += runs * recipe.output_item_count;
+        let mut total = 0;
+        let mut ingredients = vec![];
+        for ing in &recipe.ingredients {
+            let c = self.cost(&ing.item_id, ing.count * runs);
+            total += c.total;
+            ingredients.push(c);
+        }
+        self.visiting.remove(id);
+        Cost { id: *id, source: Source::craft(recipe, ingredients), quantity, total }
+    }
+
+    fn buy(&mut self, id: &ItemId, quantity: i32) -> Cost {
+        let before = self.state.bought.get(id).cloned().unwrap_or(0);
+        let source = match self.index.listings.get(id) {
+            Some(ls) => match (self.strategy.cost(ls, before + quantity), self.strategy.cost(ls, before)) {
+                (Ok(after), Ok(already)) => {
+                    self.state.bought.insert(*id, before + quantity);
+                    return Cost { id: *id, source: Source::Auction, quantity, total: after - already };
+                }
+                (Err(Error::InsufficientDepth { shortfall, .. }), _) => Unknown::Short { shortfall: std::cmp::min(shortfall, quantity) },
+                _ => Unknown::NotFetched,
+            },
+            None if self.index.missing.listings.contains(id) => Unknown::NotListed,
+            None => Unknown::NotFetched,
+        };
+        Cost { id: *id, source: Source::Unknown(source), quantity, total: 0 }
+    }
+}
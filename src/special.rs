@@ -0,0 +1,228 @@
+//! Valuations for items with no useful market price, from a rules file (see
+//! `data/special.json`).  Each rule is a small formula over numbers, other
+//! items' listings and currency values.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::client::ItemId;
+use crate::data;
+use crate::error::{Error, Result};
+use crate::index::Index;
+
+#[derive(Debug, Default, Clone)]
+pub struct Special {
+    rules: HashMap<ItemId, Expr>,
+    offerings: Option<Expr>,
+}
+
+#[derive(Deserialize)]
+struct File {
+    rules: Vec<Rule>,
+    #[serde(default)]
+    offerings: Option<String>,
+}
+
+// Entries may also carry a `name` and `note`, which are ignored.
+#[derive(Deserialize)]
+struct Rule {
+    id: ItemId,
+    value: String,
+}
+
+impl Special {
+    /// Reads and parses the rules in `path` (see `data`).
+    pub fn load(path: &Path) -> Result<Special> {
+        let file: File = data::load_or_bundled(path, include_str!("../data/special.json"))?;
+        let mut special = Special::default();
+        for r in file.rules {
+            special.rules.insert(r.id, Expr::parse(&r.value).map_err(|text| Error::Rule { item: Some(r.id), text })?);
+        }
+        if let Some(o) = file.offerings {
+            special.offerings = Some(Expr::parse(&o).map_err(|text| Error::Rule { item: None, text })?);
+        }
+        Ok(special)
+    }
+
+    /// Copper per unit of `id`, rounded up, if there's a rule for it.
+    pub fn value(&self, index: &Index, id: &ItemId) -> Option<Result<i32>> {
+        let expr = match self.rules.get(id) {
+            Some(e) => e,
+            None if index.offerings.contains(id) => self.offerings.as_ref()?,
+            None => return None,
+        };
+        Some(expr.eval(index).map(|v| v.ceil() as i32).map_err(|e| match e {
+            Error::Rule { item: None, text } => Error::Rule { item: Some(*id), text },
+            e => e,
+        }))
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Num(f64),
+    Buy(ItemId, i32),
+    Sell(ItemId, i32),
+    Currency(String),
+    Neg(Box<Expr>),
+    Op(char, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn parse(s: &str) -> std::result::Result<Expr, String> {
+        let mut p = Parser { tokens: tokenize(s)?, pos: 0 };
+        let e = p.sum()?;
+        match p.next() {
+            None => Ok(e),
+            Some(t) => Err(format!("unexpected {:?} in {:?}", t, s)),
+        }
+    }
+
+    fn eval(&self, index: &Index) -> Result<f64> {
+        let listings = |id: &ItemId| index.listings.get(id)
+            .ok_or_else(|| Error::Rule { item: None, text: format!("no listings for item {}", id.0) });
+        Ok(match self {
+            Expr::Num(n) => *n,
+            Expr::Buy(id, n) => listings(id)?.cost(*n)? as f64,
+            Expr::Sell(id, n) => listings(id)?.sale(*n)? as f64,
+            Expr::Currency(name) => index.vendor.currency_value(name)
+                .ok_or_else(|| Error::Rule { item: None, text: format!("no value for currency {:?}", name) })? as f64,
+            Expr::Neg(e) => -e.eval(index)?,
+            Expr::Op(op, a, b) => {
+                let (a, b) = (a.eval(index)?, b.eval(index)?);
+                match op {
+                    '+' => a + b,
+                    '-' => a - b,
+                    '*' => a * b,
+                    _ if b == 0.0 => return Err(Error::Rule { item: None, text: "division by zero".into() }),
+                    _ => a / b,
+                }
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Num(f64),
+    Name(String),
+    Str(String),
+    Punct(char),
+}
+
+fn tokenize(s: &str) -> std::result::Result<Vec<Token>, String> {
+    let mut out = vec![];
+    let mut chars = s.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_digit() || c == '.' {
+            let mut n = String::new();
+            while let Some(&d) = chars.peek().filter(|d| d.is_ascii_digit() || **d == '.') {
+                n.push(d);
+                chars.next();
+            }
+            out.push(Token::Num(n.parse().map_err(|_| format!("bad number {:?}", n))?));
+        } else if c.is_ascii_alphabetic() {
+            let mut n = String::new();
+            while let Some(&d) = chars.peek().filter(|d| d.is_ascii_alphanumeric() || **d == '_') {
+                n.push(d);
+                chars.next();
+            }
+            out.push(Token::Name(n));
+        } else if c == '\'' {
+            chars.next();
+            let text: String = chars.by_ref().take_while(|d| *d != '\'').collect();
+            out.push(Token::Str(text));
+        } else if "+-*/(),".contains(c) {
+            out.push(Token::Punct(c));
+            chars.next();
+        } else {
+            return Err(format!("unexpected {:?} in {:?}", c, s));
+        }
+    }
+    Ok(out)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let t = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        t
+    }
+
+    fn peek_punct(&self, cs: &str) -> Option<char> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Punct(c)) if cs.contains(*c) => Some(*c),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, c: char) -> std::result::Result<(), String> {
+        match self.next() {
+            Some(Token::Punct(p)) if p == c => Ok(()),
+            t => Err(format!("expected {:?}, found {:?}", c, t)),
+        }
+    }
+
+    // sum := product (('+' | '-') product)*
+    fn sum(&mut self) -> std::result::Result<Expr, String> {
+        let mut e = self.product()?;
+        while let Some(op) = self.peek_punct("+-") {
+            self.pos += 1;
+            e = Expr::Op(op, Box::new(e), Box::new(self.product()?));
+        }
+        Ok(e)
+    }
+
+    // product := unary (('*' | '/') unary)*
+    fn product(&mut self) -> std::result::Result<Expr, String> {
+        let mut e = self.unary()?;
+        while let Some(op) = self.peek_punct("*/") {
+            self.pos += 1;
+            e = Expr::Op(op, Box::new(e), Box::new(self.unary()?));
+        }
+        Ok(e)
+    }
+
+    // unary := '-' unary | number | '(' sum ')' | name '(' args ')'
+    fn unary(&mut self) -> std::result::Result<Expr, String> {
+        match self.next() {
+            Some(Token::Punct('-')) => Ok(Expr::Neg(Box::new(self.unary()?))),
+            Some(Token::Num(n)) => Ok(Expr::Num(n)),
+            Some(Token::Punct('(')) => {
+                let e = self.sum()?;
+                self.expect(')')?;
+                Ok(e)
+            }
+            Some(Token::Name(f)) => {
+                self.expect('(')?;
+                let e = match (f.as_str(), self.next()) {
+                    ("currency", Some(Token::Str(name))) => Expr::Currency(name),
+                    (f @ "buy", Some(Token::Num(id))) | (f @ "sell", Some(Token::Num(id))) => {
+                        let n = if self.peek_punct(",").is_some() {
+                            self.pos += 1;
+                            match self.next() {
+                                Some(Token::Num(n)) if n >= 1.0 => n as i32,
+                                t => return Err(format!("{}: expected a count, found {:?}", f, t)),
+                            }
+                        } else { 1 };
+                        let id = ItemId(id as i32);
+                        if f == "buy" { Expr::Buy(id, n) } else { Expr::Sell(id, n) }
+                    }
+                    (f, t) => return Err(format!("bad call {}({:?}...)", f, t)),
+                };
+                self.expect(')')?;
+                Ok(e)
+            }
+            t => Err(format!("expected a value, found {:?}", t)),
+        }
+    }
+}
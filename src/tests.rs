@@ -0,0 +1,608 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::TryStreamExt;
+use futures::future::{BoxFuture, FutureExt};
+use reqwest::StatusCode;
+use reqwest::header::{HeaderMap, HeaderValue, RETRY_AFTER};
+
+use crate::batch;
+use crate::cache::Cache;
+use crate::client::{Client, ItemId, Listing, Listings, Page, Recipe, RecipeId, RetryPolicy, Side, Transaction, TransactionState};
+use crate::cost::{Cost, Source, Unknown};
+use crate::error::Error;
+use crate::fee::{self, Fill};
+use crate::forge;
+use crate::history::{self, History};
+use crate::find_profits;
+use crate::index::{Index, RecipeSource};
+use crate::pricing::Strategy;
+use crate::solver;
+use crate::special::Special;
+use crate::transport::{Replay, Response, Transport};
+use crate::vendor::Vendor;
+
+// The fixtures' authenticated responses are stored under this key's hash.
+const KEY: &str = "test-key";
+// A second account in fixtures/basic, with 4 Ore, 1000 Karma and no
+// characters or dailies done.
+const SECOND_KEY: &str = "second-key";
+
+// fixtures/basic: a Widget (100) is crafted from 2 Parts (200) and a Bolt
+// (201); a Part is crafted from 3 Ore (300) and an untradeable Token (301),
+// or from 2 Bolts.
+// The account knows one recipe the API doesn't return (99) and has 6 Ore.
+const WIDGET: ItemId = ItemId(100);
+const PART: ItemId = ItemId(200);
+const ORE: ItemId = ItemId(300);
+const TOKEN: ItemId = ItemId(301);
+
+fn client(fixtures: &str) -> Client {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures").join(fixtures);
+    Client::new(Arc::new(Replay::new(&dir)), Some(KEY.to_string()), RetryPolicy::default())
+}
+
+// A file in the temp directory, removed when dropped so a failing test
+// doesn't leave it behind.
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn new(name: &str) -> TempFile {
+        let path = std::env::temp_dir().join(format!("rs-gw2-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        TempFile(path)
+    }
+
+    fn with(name: &str, contents: &str) -> TempFile {
+        let file = TempFile::new(name);
+        file.write(contents);
+        file
+    }
+
+    fn write(&self, contents: &str) {
+        std::fs::write(&self.0, contents).unwrap();
+    }
+
+    fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+async fn index(fixtures: &str) -> Index {
+    index_with_forge(fixtures, &[]).await
+}
+
+async fn index_with_forge(fixtures: &str, forge: &[Recipe]) -> Index {
+    let clients = [client(fixtures)];
+    Index::new(&clients, RecipeSource::Characters, forge, &mut Cache::default()).await.unwrap()
+}
+
+#[tokio::test]
+async fn index_records_missing_ids() {
+    let index = index("basic").await;
+    assert_eq!(index.recipes.len(), 3);
+    assert_eq!(index.items.len(), 5);
+    assert_eq!(index.listings.len(), 4);
+    assert!(index.missing.recipes.contains(&RecipeId(99)));
+    assert!(index.missing.listings.contains(&TOKEN));
+    assert_eq!(index.materials(Some("Test.1234")).unwrap().get(&ORE), Some(&6));
+    assert!(index.materials(Some("Nobody.0000")).is_err());
+}
+
+#[tokio::test]
+async fn index_all_recipes() {
+    let clients = [client("basic")];
+    let mut cache = Cache::default();
+    let index = Index::new(&clients, RecipeSource::All, &[], &mut cache).await.unwrap();
+    // Every recipe the API has, whether or not a character knows it.
+    assert_eq!(index.recipes.len(), 3);
+    assert!(index.missing.recipes.is_empty());
+    assert_eq!(cache.recipes.len(), 3);
+}
+
+#[tokio::test]
+async fn index_sums_accounts() {
+    let first = client("basic");
+    let clients = [first.with_key(Some(SECOND_KEY.to_string())), first];
+    let index = Index::new(&clients, RecipeSource::Characters, &[], &mut Cache::default()).await.unwrap();
+    assert_eq!(index.recipes.len(), 3);
+
+    assert_eq!(index.materials(None).unwrap().get(&ORE), Some(&(6 + 4)));
+    assert_eq!(index.materials(Some("Test.1234")).unwrap().get(&ORE), Some(&6));
+    assert_eq!(index.materials(Some("Second.5678")).unwrap().get(&ORE), Some(&4));
+    let karma = index.currency("Karma").unwrap().id;
+    assert_eq!(index.wallet(None).unwrap().get(&karma), Some(&(50000 + 1000)));
+    assert_eq!(index.wallet(Some("Second.5678")).unwrap().get(&karma), Some(&1000));
+    // A daily is only done once every account has done it.
+    assert!(index.daily_done(None).unwrap().is_empty());
+    assert!(index.daily_done(Some("Test.1234")).unwrap().contains(&ItemId(201)));
+}
+
+#[tokio::test]
+async fn index_reads_wallet() {
+    let index = index("basic").await;
+    assert_eq!(index.currencies.len(), 3);
+    let karma = index.currency("Karma").unwrap().id;
+    assert_eq!(index.wallet(None).unwrap().get(&karma), Some(&50000));
+    assert!(index.currency("Nonsense").is_none());
+}
+
+#[tokio::test]
+async fn index_reads_daily_crafting() {
+    let index = index("basic").await;
+    assert_eq!(index.daily.get(&ItemId(201)).map(|s| s.as_str()), Some("bolt"));
+    assert_eq!(index.daily.len(), 1);
+}
+
+#[tokio::test]
+async fn cache_discarded_on_new_build() {
+    let file = TempFile::new("cache.json");
+    let mut cache = Cache::load(file.path(), 1).unwrap();
+    let clients = [client("basic")];
+    Index::new(&clients, RecipeSource::Characters, &[], &mut cache).await.unwrap();
+    assert_eq!(cache.recipes.len(), 3);
+
+    // Saved by building the index, and kept for the same build.
+    let cache = Cache::load(file.path(), 1).unwrap();
+    assert_eq!((cache.recipes.len(), cache.items.len()), (3, 5));
+    let cache = Cache::load(file.path(), 2).unwrap();
+    assert!(cache.recipes.is_empty() && cache.items.is_empty());
+}
+
+#[tokio::test]
+async fn snapshot_round_trip() {
+    let index = index("basic").await;
+    let file = TempFile::new("snapshot.json");
+    index.save(file.path()).unwrap();
+    let loaded = Index::load(file.path()).unwrap();
+
+    assert_eq!(loaded.recipes.len(), index.recipes.len());
+    assert_eq!(loaded.items.len(), index.items.len());
+    assert_eq!(loaded.listings.len(), index.listings.len());
+    assert_eq!(loaded.materials(Some("Test.1234")).unwrap(), index.materials(Some("Test.1234")).unwrap());
+    assert_eq!(loaded.wallet(None).unwrap(), index.wallet(None).unwrap());
+    assert_eq!(loaded.daily, index.daily);
+    assert_eq!(loaded.missing.recipes, index.missing.recipes);
+    assert_eq!(loaded.missing.listings, index.missing.listings);
+    // Costs come out the same from the loaded index.
+    let strategy = Strategy::default();
+    assert_eq!(Cost::new(&loaded, &strategy, &WIDGET, 1).total, Cost::new(&index, &strategy, &WIDGET, 1).total);
+}
+
+#[tokio::test]
+async fn cost_crafts_when_cheaper() {
+    let index = index("basic").await;
+
+    // 2 Parts at 3 Ore (50c) each beats buying them at 200c.
+    let part = Cost::new(&index, &Strategy::default(), &PART, 2);
+    assert_eq!(part.total, 300);
+    assert!(matches!(part.source, Source::Recipe { .. }));
+
+    // Part's other recipe, 2 Bolts at 100c, costs more.
+    assert_eq!(index.recipes_by_item[&PART].len(), 2);
+    assert!(matches!(part.source, Source::Recipe { id: RecipeId(2), .. }));
+
+    let widget = Cost::new(&index, &Strategy::default(), &WIDGET, 1);
+    assert_eq!(widget.total, 300 + 100);
+    // Ingredients come in recipe order, and the shopping list by id.
+    match &widget.source {
+        Source::Recipe { ingredients, .. } => assert_eq!(ingredients.iter().map(|c| c.id).collect::<Vec<_>>(), vec![PART, ItemId(201)]),
+        s => panic!("widget not crafted: {:?}", s),
+    }
+    assert_eq!(widget.base_ingredients().keys().cloned().collect::<Vec<_>>(), vec![ItemId(201), ORE, TOKEN]);
+
+    let token = Cost::new(&index, &Strategy::default(), &TOKEN, 1);
+    assert!(matches!(token.source, Source::Unknown(Unknown::NotListed)));
+}
+
+#[tokio::test]
+async fn cost_uses_bank() {
+    let index = index("basic").await;
+    let mut bank = index.materials(None).unwrap();
+    let part = Cost::new_with_bank(&index, &Strategy::default(), &PART, 3, &mut bank);
+    // 6 of the 9 Ore come from the bank, the rest at 50c.
+    assert_eq!(part.total, 150);
+    assert_eq!(bank.get(&ORE), Some(&0));
+}
+
+#[tokio::test]
+async fn profits() {
+    let index = index("basic").await;
+    let (flip, bank) = find_profits(&index, None, &Strategy::default()).unwrap();
+
+    // Widget sells at 1000c less 50c + 100c fees, and costs 400c to craft.
+    assert_eq!(flip.len(), 1);
+    assert_eq!(flip[0].id, RecipeId(1));
+    assert_eq!(flip[0].value, 850 - 400);
+    // Bolts are daily-gated, and the account has made today's.
+    assert!(flip[0].daily_done.contains(&ItemId(201)));
+    // 10 Widgets wanted within 5% of the best buy, and a 1/6 spread.
+    assert_eq!(flip[0].liquidity.depth, 10);
+    assert!((flip[0].liquidity.score - 0.5 * (5.0 / 6.0)).abs() < 1e-6);
+
+    // With banked Ore valued at its 40c sale price less 2c + 4c fees.
+    assert_eq!(bank.len(), 2);
+    assert_eq!(bank[0].id, RecipeId(1));
+    assert_eq!(bank[0].value, 850 - (100 + 6 * 34));
+    assert_eq!(bank[1].id, RecipeId(2));
+    assert_eq!(bank[1].value, 127 - 3 * 34);
+}
+
+#[test]
+fn fees_per_unit() {
+    // 5% of 10c rounds up to 1c; 1c items still pay the 1c minimum.
+    assert_eq!(fee::fees(&[Fill { unit_price: 10, quantity: 3 }]), (3, 3));
+    assert_eq!(fee::fees(&[Fill { unit_price: 1, quantity: 250 }]), (250, 250));
+    // A 30c stack is charged per unit, not 5% of 30c.
+    assert_eq!(fee::fees(&[Fill { unit_price: 29, quantity: 1 }, Fill { unit_price: 1, quantity: 1 }]), (2, 4));
+}
+
+#[test]
+fn order_pricing_one_sided() {
+    let l = |unit_price, quantity| Listing { listings: 1, unit_price, quantity };
+    let sells_only = Listings { id: WIDGET, buys: vec![], sells: vec![l(100, 1), l(120, 5)] };
+    // No buy order to outbid: buying costs what buying instantly would.
+    assert_eq!(sells_only.order_cost(3).unwrap(), 100 + 2 * 120);
+    assert!(sells_only.order_cost(10).is_err());
+    let buys_only = Listings { id: WIDGET, buys: vec![l(80, 5)], sells: vec![] };
+    // No listing to undercut: list at the best buy order.
+    assert_eq!(buys_only.order_sale_price(3).unwrap(), 80);
+    let empty = Listings { id: WIDGET, buys: vec![], sells: vec![] };
+    assert!(empty.order_sale_price(1).is_err());
+}
+
+// Answers each request with the next scripted status, with an empty JSON
+// object as the body and the given Retry-After, and counts the requests.
+struct Scripted {
+    statuses: Mutex<Vec<(StatusCode, Option<&'static str>)>>,
+    calls: AtomicUsize,
+}
+
+impl Scripted {
+    fn new(statuses: &[(StatusCode, Option<&'static str>)]) -> Arc<Scripted> {
+        let statuses = statuses.iter().rev().cloned().collect();
+        Arc::new(Scripted { statuses: Mutex::new(statuses), calls: AtomicUsize::new(0) })
+    }
+
+    fn calls(&self) -> usize {
+        self.calls.load(Ordering::SeqCst)
+    }
+}
+
+impl Transport for Scripted {
+    fn get<'a>(&'a self, _path: &'a str, _key: Option<&'a str>) -> BoxFuture<'a, crate::error::Result<Response>> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        let (status, retry_after) = self.statuses.lock().unwrap().pop().expect("more requests than scripted");
+        let mut headers = HeaderMap::new();
+        if let Some(ra) = retry_after {
+            headers.insert(RETRY_AFTER, HeaderValue::from_static(ra));
+        }
+        let body = if status.is_success() { br#"{"id": 1}"#.to_vec() } else { br#"{"text": "scripted"}"#.to_vec() };
+        async move { Ok(Response { status, headers, body }) }.boxed()
+    }
+}
+
+#[tokio::test]
+async fn retries() {
+    let policy = RetryPolicy { max_attempts: 3, base_delay_ms: 1, max_delay_ms: 1, ..RetryPolicy::default() };
+    let scripted = |statuses| {
+        let t = Scripted::new(statuses);
+        (Client::new(t.clone(), None, policy.clone()), t)
+    };
+
+    // A 503 and then success.
+    let (client, t) = scripted(&[(StatusCode::SERVICE_UNAVAILABLE, None), (StatusCode::OK, None)]);
+    assert_eq!(client.build().await.unwrap().id, 1);
+    assert_eq!(t.calls(), 2);
+
+    // A 429 is retried, honoring Retry-After.
+    let (client, t) = scripted(&[(StatusCode::TOO_MANY_REQUESTS, Some("0")), (StatusCode::OK, None)]);
+    assert!(client.build().await.is_ok());
+    assert_eq!(t.calls(), 2);
+
+    // 5xxs until the attempts run out.
+    let (client, t) = scripted(&[(StatusCode::INTERNAL_SERVER_ERROR, None); 3]);
+    match client.build().await {
+        Err(Error::Http { status, .. }) => assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR),
+        r => panic!("expected a 500, got {:?}", r.map(|b| b.id)),
+    }
+    assert_eq!(t.calls(), 3);
+
+    // Other errors aren't retried.
+    let (client, t) = scripted(&[(StatusCode::NOT_FOUND, None)]);
+    assert!(client.build().await.is_err());
+    assert_eq!(t.calls(), 1);
+}
+
+#[test]
+fn retry_delays() {
+    let policy = RetryPolicy { base_delay_ms: 100, max_delay_ms: 1000, ..RetryPolicy::default() };
+    // Doubling from the base, with jitter down to half.
+    let third = policy.delay(3, None);
+    assert!(third >= Duration::from_millis(200) && third <= Duration::from_millis(400));
+    assert!(policy.delay(10, None) <= Duration::from_millis(1000));
+    // Retry-After wins when it's longer.
+    assert_eq!(policy.delay(1, Some(Duration::from_secs(5))), Duration::from_secs(5));
+}
+
+#[tokio::test]
+async fn open_orders() {
+    let client = client("basic");
+    let buys = client.transactions(TransactionState::Current, Side::Buys).await.unwrap();
+    assert_eq!(buys.len(), 2);
+    assert_eq!(buys[0].item_id, ORE);
+    assert_eq!(buys[0].quantity, 250);
+    let delivery = client.delivery().await.unwrap();
+    assert_eq!(delivery.coins, 12345);
+    assert_eq!(delivery.items[0].id, ORE);
+}
+
+#[tokio::test]
+async fn paging() {
+    let client = client("basic");
+    let path = "commerce/transactions/history/sells";
+
+    let page: Page<Transaction> = client.fetch_page(true, path, 2, 2).await.unwrap();
+    assert_eq!(page.items.len(), 1);
+    assert_eq!(page.page_total, 3);
+    assert_eq!(page.result_total, 5);
+    assert!(client.fetch_page::<Transaction>(true, path, 3, 2).await.is_err());
+
+    // Walked 2 at a time, the 5 sales take 3 pages, in order.
+    let pages: Vec<Vec<Transaction>> = client.pages(true, path, 2).try_collect().await.unwrap();
+    assert_eq!(pages.iter().map(Vec::len).collect::<Vec<_>>(), vec![2, 2, 1]);
+    assert_eq!(pages[2][0].price, 1004);
+    let all = client.transactions(TransactionState::History, Side::Sells).await.unwrap();
+    assert_eq!(all.len(), 5);
+    assert_eq!(all[4].price, 1004);
+}
+
+#[tokio::test]
+async fn price_history() {
+    let client = client("basic");
+    let file = TempFile::new("history.jsonl");
+
+    let mut first = history::snapshot(&client, &[WIDGET, ORE], 1000).await.unwrap();
+    assert_eq!(first.len(), 2);
+    history::append(file.path(), &first).unwrap();
+    for s in &mut first {
+        s.time = 2000;
+        s.buy += 10;
+    }
+    history::append(file.path(), &first).unwrap();
+
+    let h = History::load(file.path()).unwrap();
+    let all = h.stats(&WIDGET, 0).unwrap();
+    assert_eq!(all.samples, 2);
+    let buy = all.buy.unwrap();
+    assert_eq!((buy.min, buy.max, buy.mean), (1000, 1010, 1005.0));
+    assert_eq!(all.sell.unwrap().min, 1200);
+    assert_eq!(h.stats(&ORE, 1500).unwrap().buy.unwrap().min, 50);
+    assert!(h.stats(&ORE, 2500).is_none());
+    assert!(h.stats(&TOKEN, 0).is_none());
+
+    // A side with no orders in the window doesn't hide the other.
+    for s in &mut first {
+        s.time = 3000;
+        s.buy = 0;
+    }
+    history::append(file.path(), &first).unwrap();
+    let recent = History::load(file.path()).unwrap().stats(&WIDGET, 2500).unwrap();
+    assert!(recent.buy.is_none());
+    assert_eq!(recent.sell.unwrap().min, 1200);
+}
+
+#[tokio::test]
+async fn batch_walks_depth() {
+    let index = index("basic").await;
+    let recipe = index.recipe(&RecipeId(1)).unwrap();
+    let b = batch::analyze(&index, &Strategy::default(), &HashMap::new(), recipe, 50).unwrap();
+
+    // Widgets sell 10 at 1000c then 10 at 900c; Ore costs 50c for the
+    // first 16 Widgets' worth, then 60c.
+    assert_eq!(b.marginal.len(), 20);
+    assert_eq!(b.marginal[0], 450);
+    assert_eq!(b.marginal[10], 765 - 400);
+    assert_eq!(b.marginal[16], 765 - (4 * 50 + 2 * 60 + 100));
+    assert_eq!(b.marginal[17], 765 - (6 * 60 + 100));
+    assert_eq!(b.crafts, 20);
+    assert_eq!(b.profit, b.marginal.iter().sum::<i32>());
+
+    // A once-a-day Widget stops the batch after the first.
+    let limits: HashMap<_, _> = vec![(WIDGET, 1)].into_iter().collect();
+    let b = batch::analyze(&index, &Strategy::default(), &limits, recipe, 50).unwrap();
+    assert_eq!((b.crafts, b.marginal.len()), (1, 1));
+
+    // Order prices ignore depth, so they aren't batched.
+    let order = Strategy::parse("order", "order").unwrap();
+    assert!(batch::analyze(&index, &order, &HashMap::new(), recipe, 50).is_err());
+}
+
+#[tokio::test]
+async fn solver_respects_limits() {
+    let index = index("basic").await;
+    let strategy = Strategy::default();
+    let no_limits = HashMap::new();
+
+    let widget = solver::solve(&index, &strategy, &WIDGET, 1, &mut HashMap::new(), &no_limits).cost;
+    assert_eq!(widget.total, 400);
+
+    // With Parts uncraftable they're bought at 200c.
+    let limits: HashMap<_, _> = vec![(PART, 0)].into_iter().collect();
+    let widget = solver::solve(&index, &strategy, &WIDGET, 1, &mut HashMap::new(), &limits).cost;
+    assert_eq!(widget.total, 2 * 200 + 100);
+
+    // With one Part craftable, the other is bought.
+    let limits: HashMap<_, _> = vec![(PART, 1)].into_iter().collect();
+    let widget = solver::solve(&index, &strategy, &WIDGET, 1, &mut HashMap::new(), &limits).cost;
+    assert_eq!(widget.total, 150 + 200 + 100);
+    match &widget.source {
+        Source::Recipe { ingredients, .. } => assert!(matches!(ingredients[0].source, Source::Split { .. })),
+        s => panic!("widget not crafted: {:?}", s),
+    }
+
+    // 17 Widgets want 102 Ore, the last 2 from the 60c listing.
+    let widgets = solver::solve(&index, &strategy, &WIDGET, 17, &mut HashMap::new(), &no_limits).cost;
+    assert_eq!(widgets.total, 100 * 50 + 2 * 60 + 17 * 100);
+
+    // The bank is left as the chosen plan leaves it: 6 Ore used.
+    let mut bank = index.materials(None).unwrap();
+    let widget = solver::solve(&index, &strategy, &WIDGET, 1, &mut bank, &no_limits);
+    assert!(!widget.greedy);
+    assert_eq!(widget.cost.total, 100);
+    assert_eq!(bank.get(&ORE), Some(&0));
+}
+
+#[tokio::test]
+async fn vendor_table() {
+    let bundled = Vendor::load(Path::new("no-such-vendor.json")).unwrap();
+    assert_eq!(bundled.price(&ItemId(46747)), Some(150));
+
+    let file = TempFile::with("vendor.json", r#"{
+        "currency_values": { "Karma": 0.5 },
+        "offers": [
+            { "id": 201, "copper": 50, "currencies": { "Karma": 100 }, "quantity": 2 },
+            { "id": 300, "currencies": { "Laurel": 1 } }
+        ]
+    }"#);
+    let mut index = index("basic").await;
+    index.vendor = Vendor::load(file.path()).unwrap();
+
+    // Bolts at (50c + 100 Karma at 0.5c) per 2; Ore's Laurel price has no value.
+    assert_eq!(index.vendor.price(&ItemId(201)), Some(50));
+    assert_eq!(index.vendor.price(&ORE), None);
+    // Cheap Bolts make Parts' 2-Bolt recipe the better one.
+    let widget = Cost::new(&index, &Strategy::default(), &WIDGET, 1);
+    assert_eq!(widget.total, 2 * (2 * 50) + 50);
+    let bolts = Cost::new(&index, &Strategy::default(), &ItemId(201), 3);
+    assert_eq!(bolts.source.to_str(), " [VENDOR: 75c, 150 Karma]");
+}
+
+#[tokio::test]
+async fn special_rules() {
+    let file = TempFile::new("special.json");
+    let load = |rules: &str| {
+        file.write(rules);
+        Special::load(file.path())
+    };
+    assert!(load(r#"{ "rules": [{ "id": 301, "value": "2 * (buy(300)" }] }"#).is_err());
+    assert!(load(r#"{ "rules": [{ "id": 301, "value": "bogus(1)" }] }"#).is_err());
+    assert!(Special::load(Path::new("no-such-special.json")).is_ok());
+
+    let mut index = index("basic").await;
+    index.special = load(r#"{ "rules": [
+        { "id": 301, "value": "2 * buy(300, 2) / 2 + 10" },
+        { "id": 201, "value": "sell(999) * currency('Karma')" }
+    ] }"#).unwrap();
+
+    let token = Cost::new(&index, &Strategy::default(), &TOKEN, 1);
+    assert_eq!(token.total, 2 * 50 + 10);
+    assert!(matches!(token.source, Source::Special));
+    // A rule on an item with no listings is an unknown cost, not a panic.
+    let bolt = Cost::new(&index, &Strategy::default(), &ItemId(201), 1);
+    assert!(matches!(bolt.source, Source::Unknown(Unknown::Rule(_))));
+}
+
+#[tokio::test]
+async fn forge_recipes() {
+    let file = TempFile::with("forge.json", r#"{ "recipes": [
+        { "id": 1, "name": "Widget", "output_item_id": 100,
+          "ingredients": [{ "item_id": 201, "count": 2 }, { "item_id": 300, "count": 2 }] }
+    ] }"#);
+    let forge = forge::load(file.path()).unwrap();
+    assert!(forge::load(Path::new("no-such-forge.json")).unwrap().is_empty());
+
+    // 2 Bolts and 2 Ore in the forge beat crafting for 400c.
+    let index = index_with_forge("basic", &forge).await;
+    assert!(index.recipes.contains_key(&RecipeId(-1)));
+    let widget = Cost::new(&index, &Strategy::default(), &WIDGET, 1);
+    assert_eq!(widget.total, 2 * 100 + 2 * 50);
+    assert!(matches!(widget.source, Source::Forge { id: RecipeId(-1), .. }));
+
+    let (flip, _) = find_profits(&index, None, &Strategy::default()).unwrap();
+    assert_eq!(flip[0].id, RecipeId(-1));
+    assert_eq!(flip[0].value, 850 - 300);
+}
+
+#[tokio::test]
+async fn forge_cycles() {
+    // Parts from a Widget, which is made from Parts.
+    let file = TempFile::with("cycle.json", r#"{ "recipes": [
+        { "id": 2, "output_item_id": 200, "ingredients": [{ "item_id": 100, "count": 1 }] }
+    ] }"#);
+    let index = index_with_forge("basic", &forge::load(file.path()).unwrap()).await;
+    let strategy = Strategy::default();
+    assert_eq!(Cost::new(&index, &strategy, &WIDGET, 1).total, 400);
+    assert_eq!(solver::solve(&index, &strategy, &WIDGET, 1, &mut HashMap::new(), &HashMap::new()).cost.total, 400);
+
+    file.write(r#"{ "recipes": [
+        { "id": 2, "output_item_id": 100, "ingredients": [{ "item_id": 100, "count": 1 }, { "item_id": 300, "count": 2 }] }
+    ] }"#);
+    assert!(forge::load(file.path()).is_err());
+}
+
+#[tokio::test]
+async fn random_outcomes() {
+    let file = TempFile::with("outcomes.json", r#"{ "recipes": [
+        { "id": 1, "output_item_id": 100,
+          "ingredients": [{ "item_id": 201, "count": 2 }, { "item_id": 300, "count": 2 }],
+          "outcomes": [{ "item_id": 100, "count": 1, "chance": 0.5 }, { "item_id": 100, "count": 2, "chance": 0.5 }] }
+    ] }"#);
+    let forge = forge::load(file.path()).unwrap();
+    assert_eq!(forge[0].expected_count(), 1.5);
+
+    // Selling 1 Widget nets 850, and 2 net 1700; one craft costs 300.
+    let index = index_with_forge("basic", &forge).await;
+    let (flip, _) = find_profits(&index, None, &Strategy::default()).unwrap();
+    assert_eq!(flip[0].id, RecipeId(-1));
+    assert_eq!(flip[0].value, 1275 - 300);
+    let spread = flip[0].outcomes.unwrap();
+    assert_eq!((spread.expected, spread.best, spread.worst), (975, 1400, 550));
+    assert_eq!(spread.deviation(), 425);
+
+    file.write(r#"{ "recipes": [
+        { "id": 1, "output_item_id": 100, "ingredients": [{ "item_id": 201, "count": 2 }],
+          "outcomes": [{ "item_id": 100, "count": 1, "chance": 0.5 }] }
+    ] }"#);
+    assert!(forge::load(file.path()).is_err());
+}
+
+#[tokio::test]
+async fn promotion() {
+    // Like promoting materials: a Widget and 2 Ore make 2 or 3 Widgets, a
+    // net gain of 1 or 2.
+    let file = TempFile::with("promotion.json", r#"{ "recipes": [
+        { "id": 1, "output_item_id": 100,
+          "ingredients": [{ "item_id": 100, "count": 1 }, { "item_id": 300, "count": 2 }],
+          "outcomes": [{ "item_id": 100, "count": 2, "chance": 0.5 }, { "item_id": 100, "count": 3, "chance": 0.5 }] }
+    ] }"#);
+    let forge = forge::load(file.path()).unwrap();
+    assert_eq!(forge[0].expected_count(), 1.5);
+    assert_eq!(forge[0].ingredients.len(), 1);
+
+    let index = index_with_forge("basic", &forge).await;
+    let widget = Cost::new(&index, &Strategy::default(), &WIDGET, 1);
+    assert_eq!(widget.total, 2 * 50);
+    let (flip, _) = find_profits(&index, None, &Strategy::default()).unwrap();
+    assert_eq!(flip[0].id, RecipeId(-1));
+    assert_eq!(flip[0].value, 1275 - 100);
+    let spread = flip[0].outcomes.unwrap();
+    assert_eq!((spread.best, spread.worst), (1700 - 100, 850 - 100));
+
+    // Every outcome must come out ahead.
+    file.write(r#"{ "recipes": [
+        { "id": 1, "output_item_id": 100,
+          "ingredients": [{ "item_id": 100, "count": 1 }, { "item_id": 300, "count": 2 }],
+          "outcomes": [{ "item_id": 100, "count": 1, "chance": 0.5 }, { "item_id": 100, "count": 3, "chance": 0.5 }] }
+    ] }"#);
+    assert!(forge::load(file.path()).is_err());
+}
@@ -0,0 +1,289 @@
+use std::collections::HashSet;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use futures::future::{BoxFuture, FutureExt};
+use reqwest::StatusCode;
+use reqwest::header::{HeaderMap, HeaderValue};
+use serde_json::Value;
+
+use crate::error::{self, Result};
+
+/// A raw API response.
+pub struct Response {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+/// Carries requests to the API.  `path` is relative to the `/v2/` root and
+/// may include a query string.
+pub trait Transport: Send + Sync {
+    fn get<'a>(&'a self, path: &'a str, key: Option<&'a str>) -> BoxFuture<'a, Result<Response>>;
+}
+
+/// Talks to the live API.
+pub struct Http {
+    reqw: reqwest::Client,
+}
+
+impl Http {
+    /// Requests taking longer than `timeout` fail, and are retried like
+    /// other transient errors.
+    pub fn new(timeout: Duration) -> Result<Self> {
+        Ok(Http { reqw: reqwest::Client::builder().timeout(timeout).build()? })
+    }
+}
+
+impl Transport for Http {
+    fn get<'a>(&'a self, path: &'a str, key: Option<&'a str>) -> BoxFuture<'a, Result<Response>> {
+        async move {
+            let mut req = self.reqw.get(&format!("https://api.guildwars2.com/v2/{}", path))
+                .query(&[("v", "latest")]);
+            if let Some(k) = key {
+                req = req.query(&[("access_token", k)]);
+            }
+            let res = req.send().await?;
+            let status = res.status();
+            let headers = res.headers().clone();
+            let body = res.bytes().await?.to_vec();
+            Ok(Response { status, headers, body })
+        }.boxed()
+    }
+}
+
+/// Passes requests through to another transport, saving successful responses
+/// as fixtures under `dir` for `Replay`.
+pub struct Record {
+    inner: Box<dyn Transport>,
+    dir: PathBuf,
+    // Bulk fixtures are read-modify-written by concurrent requests.
+    lock: Mutex<()>,
+}
+
+impl Record {
+    pub fn new(inner: Box<dyn Transport>, dir: &Path) -> Self {
+        Record { inner, dir: dir.to_owned(), lock: Mutex::new(()) }
+    }
+
+    fn save(&self, path: &str, key: Option<&str>, res: &Response) -> Result<()> {
+        let fixture = Fixture::new(&self.dir, path, key);
+        let _guard = self.lock.lock().unwrap();
+        std::fs::create_dir_all(fixture.file.parent().unwrap())?;
+        let value: Value = match &fixture.kind {
+            Kind::Plain => error::from_json(path, &res.body[..])?,
+            Kind::Bulk(_) => {
+                let mut objects = fixture.read_objects()?;
+                let new: Vec<Value> = error::from_json(path, &res.body[..])?;
+                let new_ids: HashSet<String> = new.iter().map(|v| v["id"].to_string()).collect();
+                objects.retain(|v| !new_ids.contains(&v["id"].to_string()));
+                objects.extend(new);
+                Value::Array(objects)
+            }
+            Kind::Paged { page, page_size } => {
+                // Pages are written into place in the complete list.
+                let mut objects = fixture.read_objects()?;
+                let new: Vec<Value> = error::from_json(path, &res.body[..])?;
+                let start = page * page_size;
+                if objects.len() < start + new.len() {
+                    objects.resize(start + new.len(), Value::Null);
+                }
+                for (i, v) in new.into_iter().enumerate() {
+                    objects[start + i] = v;
+                }
+                let total = res.headers.get("X-Result-Total")
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.parse::<usize>().ok());
+                if let Some(t) = total {
+                    objects.truncate(t);
+                }
+                Value::Array(objects)
+            }
+        };
+        let file = std::fs::File::create(&fixture.file)?;
+        serde_json::to_writer_pretty(BufWriter::new(file), &value).map_err(std::io::Error::from)?;
+        Ok(())
+    }
+}
+
+impl Transport for Record {
+    fn get<'a>(&'a self, path: &'a str, key: Option<&'a str>) -> BoxFuture<'a, Result<Response>> {
+        async move {
+            let res = self.inner.get(path, key).await?;
+            if res.status == StatusCode::OK || res.status == StatusCode::PARTIAL_CONTENT {
+                self.save(path, key, &res)?;
+            }
+            Ok(res)
+        }.boxed()
+    }
+}
+
+/// Serves responses from fixtures written by `Record` (or by hand), without
+/// any network access.
+pub struct Replay {
+    dir: PathBuf,
+}
+
+impl Replay {
+    pub fn new(dir: &Path) -> Self {
+        Replay { dir: dir.to_owned() }
+    }
+
+    fn respond(&self, path: &str, key: Option<&str>) -> Result<Response> {
+        let fixture = Fixture::new(&self.dir, path, key);
+        let mut headers = HeaderMap::new();
+        let (status, body) = match &fixture.kind {
+            _ if !fixture.file.exists() && !fixture.kind.is_bulk() => {
+                error_body(StatusCode::NOT_FOUND, &format!("no fixture at {}", fixture.file.display()))?
+            }
+            Kind::Plain => (StatusCode::OK, std::fs::read(&fixture.file)?),
+            Kind::Paged { page, page_size } => {
+                let objects = fixture.read_objects()?;
+                let page_total = objects.len().div_ceil(*page_size);
+                let start = page * page_size;
+                if *page > 0 && *page >= page_total {
+                    let text = format!("page out of range. Use page values 0 - {}.", page_total.saturating_sub(1));
+                    error_body(StatusCode::BAD_REQUEST, &text)?
+                } else {
+                    let end = std::cmp::min(start + page_size, objects.len());
+                    let found = &objects[start.min(end)..end];
+                    for (name, value) in &[
+                        ("X-Page-Total", page_total),
+                        ("X-Page-Size", *page_size),
+                        ("X-Result-Total", objects.len()),
+                        ("X-Result-Count", found.len()),
+                    ] {
+                        headers.insert(*name, HeaderValue::from(*value as u64));
+                    }
+                    (StatusCode::OK, serde_json::to_vec(found).map_err(std::io::Error::from)?)
+                }
+            }
+            Kind::Bulk(ids) => {
+                let found: Vec<Value> = fixture.read_objects()?.into_iter()
+                    .filter(|v| ids.contains(&v["id"].to_string()))
+                    .collect();
+                // Mirror the API: 206 if some ids are unknown, 404 if all are.
+                let status = if found.is_empty() {
+                    StatusCode::NOT_FOUND
+                } else if found.len() < ids.len() {
+                    StatusCode::PARTIAL_CONTENT
+                } else {
+                    StatusCode::OK
+                };
+                if found.is_empty() {
+                    error_body(status, "all ids provided are invalid")?
+                } else {
+                    (status, serde_json::to_vec(&found).map_err(std::io::Error::from)?)
+                }
+            }
+        };
+        Ok(Response { status, headers, body })
+    }
+}
+
+fn error_body(status: StatusCode, text: &str) -> Result<(StatusCode, Vec<u8>)> {
+    let body = serde_json::to_vec(&serde_json::json!({ "text": text })).map_err(std::io::Error::from)?;
+    Ok((status, body))
+}
+
+impl Transport for Replay {
+    fn get<'a>(&'a self, path: &'a str, key: Option<&'a str>) -> BoxFuture<'a, Result<Response>> {
+        let res = self.respond(path, key);
+        async move { res }.boxed()
+    }
+}
+
+/// Where the fixture for a request lives.  Bulk `?ids=` requests share one
+/// file per endpoint holding every object seen, so fixtures don't depend on
+/// how ids were chunked; paged requests share one file holding the complete
+/// list, which replay splits into pages.  Authenticated requests go in a
+/// subdirectory named for a hash of the key, so the key itself isn't written
+/// to disk.
+struct Fixture {
+    file: PathBuf,
+    kind: Kind,
+}
+
+enum Kind {
+    Plain,
+    Bulk(HashSet<String>),
+    Paged { page: usize, page_size: usize },
+}
+
+impl Kind {
+    fn is_bulk(&self) -> bool {
+        matches!(self, Kind::Bulk(_))
+    }
+}
+
+impl Fixture {
+    fn new(dir: &Path, path: &str, key: Option<&str>) -> Self {
+        let mut file = dir.to_owned();
+        if let Some(k) = key {
+            file.push(key_hash(k));
+        }
+        let (base, query) = match path.find('?') {
+            Some(i) => (&path[..i], &path[i+1..]),
+            None => (path, ""),
+        };
+        let base = sanitize(&base.replace('/', "."));
+
+        let mut ids = None;
+        let mut page = None;
+        let mut page_size = None;
+        let mut rest = vec![];
+        for param in query.split('&').filter(|p| !p.is_empty()) {
+            let mut kv = param.splitn(2, '=');
+            match (kv.next(), kv.next()) {
+                (Some("ids"), Some(v)) => ids = Some(v.split(',').map(|s| s.to_string()).collect()),
+                (Some("page"), Some(v)) => page = v.parse::<usize>().ok(),
+                (Some("page_size"), Some(v)) => page_size = v.parse::<usize>().ok(),
+                _ => rest.push(param),
+            }
+        }
+        let kind = match (ids, page, page_size) {
+            (Some(ids), None, None) => Kind::Bulk(ids),
+            (None, Some(page), Some(page_size)) if page_size > 0 => Kind::Paged { page, page_size },
+            _ => {
+                rest = query.split('&').filter(|p| !p.is_empty()).collect();
+                Kind::Plain
+            }
+        };
+
+        let mut name = base;
+        if !rest.is_empty() {
+            name.push('.');
+            name.push_str(&sanitize(&rest.join("&")));
+        }
+        if kind.is_bulk() {
+            name.push_str(".ids");
+        }
+        name.push_str(".json");
+        file.push(name);
+        Fixture { file, kind }
+    }
+
+    fn read_objects(&self) -> Result<Vec<Value>> {
+        if !self.file.exists() {
+            return Ok(vec![]);
+        }
+        let file = std::fs::File::open(&self.file)?;
+        error::from_json(&self.file.display().to_string(), BufReader::new(file))
+    }
+}
+
+fn sanitize(s: &str) -> String {
+    s.chars().map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' }).collect()
+}
+
+// FNV-1a; stable across runs and platforms, unlike `DefaultHasher`.
+fn key_hash(key: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in key.bytes() {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
@@ -0,0 +1,100 @@
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::client::ItemId;
+use crate::data;
+use crate::error::Result;
+
+/// Prices of items bought from NPC vendors, read from a data file (see
+/// `data/vendor.json`).
+#[derive(Debug, Default, Clone)]
+pub struct Vendor {
+    offers: HashMap<ItemId, Offer>,
+    currency_values: HashMap<String, f32>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct File {
+    #[serde(default)]
+    currency_values: HashMap<String, f32>,
+    offers: Vec<Offer>,
+}
+
+/// What a vendor charges for `quantity` of an item.  Entries may also carry
+/// a `name`, which is ignored.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Offer {
+    pub id: ItemId,
+    #[serde(default)]
+    pub copper: i32,
+    #[serde(default)]
+    pub currencies: HashMap<String, i32>,
+    #[serde(default = "one")]
+    pub quantity: i32,
+}
+
+fn one() -> i32 { 1 }
+
+impl Offer {
+    /// Copper spent on `quantity` units, in proportion to the offer's and
+    /// rounded up.
+    pub fn coin(&self, quantity: i32) -> i32 {
+        let per = std::cmp::max(1, self.quantity);
+        (self.copper * quantity + per - 1) / per
+    }
+
+    /// Currency spent on `quantity` units, by name, in proportion to the
+    /// offer's and rounded up to whole units of currency.
+    pub fn amounts(&self, quantity: i32) -> Vec<(String, i32)> {
+        let per = std::cmp::max(1, self.quantity);
+        let mut out: Vec<_> = self.currencies.iter().map(|(c, n)| (c.clone(), (n * quantity + per - 1) / per)).collect();
+        out.sort();
+        out
+    }
+}
+
+impl Vendor {
+    /// Reads the table in `path` (see `data`).
+    pub fn load(path: &Path) -> Result<Vendor> {
+        let file: File = data::load_or_bundled(path, include_str!("../data/vendor.json"))?;
+        Ok(Vendor {
+            offers: file.offers.into_iter().map(|o| (o.id, o)).collect(),
+            currency_values: file.currency_values,
+        })
+    }
+
+    /// Copper value of one unit of a currency.
+    pub fn currency_value(&self, name: &str) -> Option<f32> {
+        self.currency_values.get(name).cloned()
+    }
+
+    /// Overrides currency values, e.g. from the config file.
+    pub fn set_currency_values(&mut self, values: &HashMap<String, f32>) {
+        self.currency_values.extend(values.iter().map(|(k, v)| (k.clone(), *v)));
+    }
+
+    /// Every currency named in the table.
+    pub fn currency_names(&self) -> BTreeSet<&str> {
+        self.currency_values.keys().map(|s| s.as_str())
+            .chain(self.offers.values().flat_map(|o| o.currencies.keys().map(|s| s.as_str())))
+            .collect()
+    }
+
+    pub fn offer(&self, id: &ItemId) -> Option<&Offer> {
+        self.offers.get(id)
+    }
+
+    /// Copper per unit of `id`, rounded up, counting currencies at their
+    /// configured value.  `None` if no vendor sells it, or it costs a
+    /// currency with no value.
+    pub fn price(&self, id: &ItemId) -> Option<i32> {
+        let offer = self.offers.get(id)?;
+        let mut total = offer.copper as f32;
+        for (currency, amount) in &offer.currencies {
+            total += self.currency_values.get(currency)? * (*amount as f32);
+        }
+        Some((total / std::cmp::max(1, offer.quantity) as f32).ceil() as i32)
+    }
+}